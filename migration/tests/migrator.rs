@@ -0,0 +1,54 @@
+//! Runs every migration up and down against each enabled backend.
+//!
+//! SQLite runs in memory. PostgreSQL and MySQL run when their feature is
+//! enabled and `POSTGRES_DATABASE_URL` / `MYSQL_DATABASE_URL` point to a
+//! scratch database, and are skipped otherwise.
+use migration::{Migrator, MigratorTrait};
+use sea_orm_migration::sea_orm::Database;
+use sea_orm_migration::SchemaManager;
+
+async fn check_up_and_down(url: &str) {
+  let db = Database::connect(url).await.unwrap();
+  let manager = SchemaManager::new(&db);
+
+  Migrator::reset(&db).await.unwrap();
+  assert!(!manager.has_table("task").await.unwrap());
+
+  Migrator::up(&db, None).await.unwrap();
+  assert!(manager.has_table("task").await.unwrap());
+  assert!(Migrator::get_pending_migrations(&db)
+    .await
+    .unwrap()
+    .is_empty());
+
+  Migrator::down(&db, None).await.unwrap();
+  assert!(!manager.has_table("task").await.unwrap());
+  assert_eq!(
+    Migrator::get_pending_migrations(&db).await.unwrap().len(),
+    Migrator::migrations().len()
+  );
+}
+
+#[cfg(feature = "sqlite")]
+#[async_std::test]
+async fn sqlite() {
+  check_up_and_down("sqlite::memory:").await;
+}
+
+#[cfg(feature = "postgres")]
+#[async_std::test]
+async fn postgres() {
+  match std::env::var("POSTGRES_DATABASE_URL") {
+    Ok(url) => check_up_and_down(&url).await,
+    Err(_) => eprintln!("POSTGRES_DATABASE_URL is not set, skipping"),
+  }
+}
+
+#[cfg(feature = "mysql")]
+#[async_std::test]
+async fn mysql() {
+  match std::env::var("MYSQL_DATABASE_URL") {
+    Ok(url) => check_up_and_down(&url).await,
+    Err(_) => eprintln!("MYSQL_DATABASE_URL is not set, skipping"),
+  }
+}
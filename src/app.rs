@@ -0,0 +1,314 @@
+//! # Application Lifecycle
+//!
+//! Binaries describe their application by implementing [`Hooks`], and hand
+//! it to [`start`] (or [`run`], which also reports startup failures and
+//! picks the exit code). The hooks are called in this order:
+//!
+//! 1. [`Hooks::load_config`] for the selected [`Environment`];
+//! 2. [`Hooks::boot`] to connect the database and build the [`AppContext`];
+//! 3. [`Initializer::before_run`] of every [`Hooks::initializers`];
+//! 4. [`Hooks::routes`], then [`Initializer::after_routes`] and
+//!    [`Hooks::after_routes`] on the resulting router;
+//! 5. the server runs until `SIGINT` or `SIGTERM`, then lets the requests in
+//!    flight finish, see [`crate::shutdown`];
+//! 6. [`Hooks::on_shutdown`], then the registered shutdown hooks, the
+//!    database closing last.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use pos_rust_local_backend::app::{self, Hooks};
+//! use pos_rust_local_backend::config::app_context::AppContext;
+//! use pos_rust_local_backend::config::routes_config::AppRoutes;
+//! use pos_rust_local_backend::controllers;
+//!
+//! struct App;
+//!
+//! impl Hooks for App {
+//!     fn routes(_ctx: &AppContext) -> AppRoutes {
+//!         AppRoutes::with_default_routes().add_route(controllers::tasks_controller::routes())
+//!     }
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() -> std::process::ExitCode {
+//!     app::run::<App>().await
+//! }
+//! ```
+use crate::config::app_context::AppContext;
+use crate::config::db::db_connection;
+use crate::config::listener::AppListener;
+use crate::config::routes_config::AppRoutes;
+use crate::config::{Config, Environment, ListenerKind};
+use crate::errors::StartupErrorKind;
+use crate::{logger, shutdown, Error, Result};
+use async_trait::async_trait;
+use axum::Router;
+use colored::Colorize;
+use std::io;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// The application's extension points, called by [`start`].
+#[async_trait]
+pub trait Hooks: Send + Sync + 'static {
+  /// Loads the configuration of the selected environment.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error when the configuration is invalid.
+  async fn load_config(environment: &Environment) -> Result<Config> {
+    Config::load(environment)
+  }
+
+  /// Connects the database and builds the [`AppContext`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error when the database cannot be prepared.
+  async fn boot(config: Config) -> Result<AppContext> {
+    db_connection(config).await
+  }
+
+  /// The routes of the application.
+  fn routes(ctx: &AppContext) -> AppRoutes;
+
+  /// Customizes the router once every route is registered, e.g. to add
+  /// application-wide layers.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error to abort the startup.
+  async fn after_routes(router: Router, _ctx: &AppContext) -> Result<Router> {
+    Ok(router)
+  }
+
+  /// The initializers to run, in order.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error to abort the startup.
+  async fn initializers(_ctx: &AppContext) -> Result<Vec<Box<dyn Initializer>>> {
+    Ok(vec![])
+  }
+
+  /// Called once the requests in flight are drained, before the shutdown
+  /// hooks registered on [`AppContext::shutdown`] run.
+  async fn on_shutdown(_ctx: &AppContext) {}
+
+  /// Inserts the initial data, run by `db seed`. Should be idempotent.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error when the data cannot be inserted.
+  async fn seed(_ctx: &AppContext) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// A subsystem that prepares itself when the application starts, for
+/// example by registering an extension in the [`AppContext`].
+#[async_trait]
+pub trait Initializer: Send + Sync {
+  /// Name used in logs and errors.
+  fn name(&self) -> String;
+
+  /// Runs before the routes are built.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error to abort the startup.
+  async fn before_run(&self, _ctx: &mut AppContext) -> Result<()> {
+    Ok(())
+  }
+
+  /// Customizes the router once every route is registered.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error to abort the startup.
+  async fn after_routes(&self, router: Router, _ctx: &AppContext) -> Result<Router> {
+    Ok(router)
+  }
+}
+
+/// Runs the initializers and builds the router of the application.
+///
+/// # Errors
+///
+/// Returns the first error of an initializer or hook.
+pub async fn create_app<H: Hooks>(ctx: &mut AppContext) -> Result<Router> {
+  let initializers = H::initializers(ctx).await?;
+  for initializer in &initializers {
+    tracing::debug!(initializer = initializer.name(), "before_run");
+    initializer.before_run(ctx).await?;
+  }
+
+  let mut router = H::routes(ctx).into_router(ctx)?;
+  for initializer in &initializers {
+    tracing::debug!(initializer = initializer.name(), "after_routes");
+    router = initializer.after_routes(router, ctx).await?;
+  }
+  H::after_routes(router, ctx).await
+}
+
+/// Boots the application in the environment of `APP_ENV`, see
+/// [`Environment::resolve`], and serves it until a shutdown signal.
+///
+/// # Errors
+///
+/// Returns an [`Error::Startup`] describing what prevented the server from
+/// starting, or any error of the hooks.
+pub async fn start<H: Hooks>() -> Result<()> {
+  dotenvy::dotenv().ok();
+  start_with::<H>(&Environment::resolve()).await
+}
+
+/// Like [`start`], for an environment selected by the caller.
+///
+/// # Errors
+///
+/// See [`start`].
+pub async fn start_with<H: Hooks>(environment: &Environment) -> Result<()> {
+  // Load the configuration profile of the selected environment
+  let config = H::load_config(environment).await?;
+
+  // Initialize tracing
+  logger::init(&config);
+
+  // Initialize the database connection
+  let mut ctx = H::boot(config).await?;
+  // Registered first, so it runs after every other shutdown hook
+  ctx.shutdown.register("database", |ctx| async move {
+    if let Some(replica) = ctx.replica {
+      replica.close().await?;
+    }
+    ctx.db.close().await?;
+    tracing::info!("Database connection closed successfully");
+    Ok(())
+  });
+
+  // Create the router and the listener, closing the database on failure
+  let (app, listener) = match prepare::<H>(&mut ctx).await {
+    Ok(prepared) => prepared,
+    Err(err) => {
+      ctx.shutdown.run_hooks(&ctx).await;
+      return Err(err);
+    }
+  };
+  println!("Server running on {}", listener);
+
+  // Start the server with graceful shutdown
+  let served = serve(listener, app, &ctx)
+    .await
+    .map_err(|err| Error::startup(StartupErrorKind::Server, "server", err));
+
+  H::on_shutdown(&ctx).await;
+  ctx.shutdown.run_hooks(&ctx).await;
+  println!("Shutting down gracefully...");
+
+  served
+}
+
+/// Builds the router of the booted application and binds its listener.
+async fn prepare<H: Hooks>(ctx: &mut AppContext) -> Result<(Router, AppListener)> {
+  let app = create_app::<H>(ctx).await?;
+  let listener = AppListener::bind(&ctx.config.server).await.map_err(|err| {
+    let setting = match ctx.config.server.listener {
+      ListenerKind::Tcp => "server.binding/server.port",
+      ListenerKind::Unix => "server.unix_socket",
+      ListenerKind::Systemd => "server.listener",
+    };
+    Error::startup(StartupErrorKind::Listener, setting, err)
+  })?;
+  let listener = match &ctx.config.server.tls {
+    Some(tls) => listener.with_tls(tls)?,
+    None => listener,
+  };
+  Ok((app, listener))
+}
+
+/// How long the aborted requests get to end once the drain timeout passed.
+const ABORT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Serves until a shutdown signal, then gives the requests in flight
+/// `server.drain_timeout` to finish.
+async fn serve(listener: AppListener, app: Router, ctx: &AppContext) -> io::Result<()> {
+  let (stop, stopped) = oneshot::channel::<()>();
+  let server = listener.serve(app, async {
+    let _ = stopped.await;
+  });
+  tokio::pin!(server);
+
+  tokio::select! {
+    result = &mut server => return result,
+    () = shutdown::signal() => {}
+  }
+  tracing::info!(
+    in_flight = ctx.shutdown.in_flight(),
+    "Shutting down gracefully..."
+  );
+  shutdown::force_exit_on_signal();
+  let _ = stop.send(());
+
+  let deadline = Duration::from_millis(ctx.config.server.drain_timeout);
+  if let Ok(result) = tokio::time::timeout(deadline, &mut server).await {
+    return result;
+  }
+  tracing::warn!(
+    in_flight = ctx.shutdown.in_flight(),
+    "drain timeout reached, aborting the requests in flight"
+  );
+  // The connections close once their request is aborted, so the hooks, the
+  // database closing included, only run when nothing uses them anymore
+  ctx.shutdown.abort_in_flight();
+  if let Ok(result) = tokio::time::timeout(ABORT_TIMEOUT, server).await {
+    return result;
+  }
+  tracing::error!(
+    in_flight = ctx.shutdown.in_flight(),
+    "connections still open after aborting their requests"
+  );
+  Ok(())
+}
+
+/// Runs [`start`], printing the startup failure if any, and returns the exit
+/// code matching it.
+pub async fn run<H: Hooks>() -> ExitCode {
+  match start::<H>().await {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(err) => {
+      print_startup_error(&err);
+      ExitCode::from(err.exit_code())
+    }
+  }
+}
+
+/// Prints which setting prevented the server from starting, and why.
+pub fn print_startup_error(err: &Error) {
+  let Error::Startup {
+    kind,
+    setting,
+    source,
+  } = err
+  else {
+    eprintln!("{} {err}", "error:".red().bold());
+    return;
+  };
+  eprintln!("{} {kind}", "error:".red().bold());
+  eprintln!("  {} {setting}", "setting:".bold());
+  eprintln!("  {} {source}", "reason:".bold());
+  let mut previous = source.to_string();
+  let mut cause = std::error::Error::source(source.as_ref());
+  while let Some(err) = cause {
+    // Wrapping errors often repeat the message of their source
+    let message = err.to_string();
+    if !previous.contains(&message) {
+      eprintln!("  {} {message}", "caused by:".bold());
+    }
+    previous = message;
+    cause = err.source();
+  }
+  eprintln!("  {} {}", "exit code:".bold(), kind.exit_code());
+}
@@ -10,6 +10,7 @@ use moka::Expiry;
 
 use super::CacheDriver;
 use crate::cache::CacheResult;
+use crate::config::InMemCacheConfig;
 
 /// Creates a new instance of the in-memory cache driver, with a default Loco
 /// configuration.
@@ -19,8 +20,19 @@ use crate::cache::CacheResult;
 /// A boxed [`CacheDriver`] instance.
 #[must_use]
 pub fn new() -> Box<dyn CacheDriver> {
+  with_config(&InMemCacheConfig::default())
+}
+
+/// Creates a new instance of the in-memory cache driver sized from the
+/// given configuration.
+///
+/// # Returns
+///
+/// A boxed [`CacheDriver`] instance.
+#[must_use]
+pub fn with_config(config: &InMemCacheConfig) -> Box<dyn CacheDriver> {
   let cache: Cache<String, (Expiration, String)> = Cache::builder()
-    .max_capacity(32 * 1024 * 1024)
+    .max_capacity(config.max_capacity)
     .expire_after(InMemExpiry)
     .build();
   Inmem::from(cache)
@@ -5,10 +5,24 @@ use async_trait::async_trait;
 use std::time::Duration;
 
 use super::CacheResult;
+use crate::config::CacheConfig;
 
 pub mod inmem;
 pub mod null;
 
+/// Creates the cache driver selected in the configuration.
+///
+/// # Returns
+///
+/// A boxed [`CacheDriver`] instance.
+#[must_use]
+pub fn from_config(config: &CacheConfig) -> Box<dyn CacheDriver> {
+  match config {
+    CacheConfig::InMem(config) => inmem::with_config(config),
+    CacheConfig::Null => null::new(),
+  }
+}
+
 /// Trait representing a cache driver.
 #[async_trait]
 pub trait CacheDriver: Sync + Send {
@@ -61,4 +75,10 @@ pub trait CacheDriver: Sync + Send {
   /// Returns a [`super::CacheError`] if there is an error during the
   /// operation.
   async fn clear(&self) -> CacheResult<()>;
+
+  /// Whether the driver stores nothing, like the `null` driver, so that
+  /// reads always miss and writes fail.
+  fn is_null(&self) -> bool {
+    false
+  }
 }
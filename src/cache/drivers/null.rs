@@ -95,4 +95,8 @@ impl CacheDriver for Null {
       "Operation not supported by null cache".into(),
     ))
   }
+
+  fn is_null(&self) -> bool {
+    true
+  }
 }
@@ -127,22 +127,21 @@ impl Cache {
   ///
   /// # Example
   /// ```
-  ///  ///
-  ///
   /// use pos_rust_local_backend::config::app_context::get_app_context;
   ///
-  /// pub async fn get_or_insert(){
-  ///    let app_ctx = get_app_context().await;
-  ///    let res = app_ctx.cache.get_or_insert("key", async {
-  ///            Ok("value".to_string())
-  ///     }).await.unwrap();
-  ///    assert_eq!(res, "value");
-  /// }
+  /// # #[tokio::main]
+  /// # async fn main() {
+  /// let app_ctx = get_app_context().await;
+  /// let res = app_ctx.cache.get_or_insert("key", async {
+  ///         Ok("value".to_string())
+  ///  }).await.unwrap();
+  /// assert_eq!(res, "value");
+  /// # }
   /// ```
   ///
   /// # Errors
   ///
-  /// A [`LocoResult`] indicating the success of the operation.
+  /// A [`crate::Result`] indicating the success of the operation.
   pub async fn get_or_insert<F>(&self, key: &str, f: F) -> Result<String>
   where
     F: Future<Output = Result<String>> + Send,
@@ -162,22 +161,23 @@ impl Cache {
   ///
   /// # Example
   /// ```
-  /// use std::time::Duration;    ///
+  /// use std::time::Duration;
   ///
   /// use pos_rust_local_backend::config::app_context::get_app_context;
   ///
-  /// pub async fn get_or_insert(){
-  ///    let app_ctx = get_app_context().await;
-  ///    let res = app_ctx.cache.get_or_insert_with_expiry("key", Duration::from_secs(300), async {
-  ///            Ok("value".to_string())
-  ///     }).await.unwrap();
-  ///    assert_eq!(res, "value");
-  /// }
+  /// # #[tokio::main]
+  /// # async fn main() {
+  /// let app_ctx = get_app_context().await;
+  /// let res = app_ctx.cache.get_or_insert_with_expiry("key", Duration::from_secs(300), async {
+  ///         Ok("value".to_string())
+  ///  }).await.unwrap();
+  /// assert_eq!(res, "value");
+  /// # }
   /// ```
   ///
   /// # Errors
   ///
-  /// A [`LocoResult`] indicating the success of the operation.
+  /// A [`crate::Result`] indicating the success of the operation.
   pub async fn get_or_insert_with_expiry<F>(
     &self,
     key: &str,
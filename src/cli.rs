@@ -0,0 +1,255 @@
+//! # Command Line
+//!
+//! The management commands of the application, shared by every binary that
+//! describes its application with [`Hooks`]:
+//!
+//! ```text
+//! pos_rust_local_backend [-e <env>] start
+//! pos_rust_local_backend [-e <env>] routes
+//! pos_rust_local_backend [-e <env>] db migrate|status|seed
+//! pos_rust_local_backend [-e <env>] db rollback [--steps <n>]
+//! pos_rust_local_backend [-e <env>] doctor
+//! ```
+//!
+//! Without a command the server is started.
+use crate::app::{self, Hooks};
+use crate::config::app_context::AppContext;
+use crate::config::{Config, Environment, MigrationPolicy};
+use crate::{doctor, Result};
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::DatabaseConnection;
+use std::process::ExitCode;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+  /// The environment whose configuration is loaded.
+  #[arg(
+    global = true,
+    short,
+    long,
+    env = crate::config::environment::ENV_VAR,
+    default_value_t = Environment::Development
+  )]
+  environment: Environment,
+
+  #[command(subcommand)]
+  command: Option<Commands>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+  /// Start the server (the default).
+  Start,
+  /// List the registered routes.
+  Routes,
+  /// Manage the database.
+  Db {
+    #[command(subcommand)]
+    command: DbCommands,
+  },
+  /// Check the environment, the database and the migrations.
+  Doctor,
+}
+
+#[derive(Debug, Subcommand)]
+enum DbCommands {
+  /// Apply the pending migrations.
+  Migrate,
+  /// Show the status of every migration.
+  Status,
+  /// Revert the last applied migrations.
+  Rollback {
+    /// Number of migrations to revert.
+    #[arg(short, long, default_value_t = 1)]
+    steps: u32,
+  },
+  /// Insert the initial data.
+  Seed,
+}
+
+/// Parses the process arguments and runs the selected command.
+///
+/// Returns the exit code of the command, see [`app::run`].
+pub async fn main<H: Hooks>() -> ExitCode {
+  dotenvy::dotenv().ok();
+  let cli = Cli::parse();
+  let environment = cli.environment;
+
+  let result = match cli.command.unwrap_or(Commands::Start) {
+    Commands::Start => app::start_with::<H>(&environment).await,
+    Commands::Routes => routes::<H>(&environment).await,
+    Commands::Db { command } => db::<H>(&environment, command).await,
+    Commands::Doctor => {
+      return match H::load_config(&environment).await {
+        Ok(config) => run_doctor(&config).await,
+        Err(err) => {
+          app::print_startup_error(&err);
+          ExitCode::from(err.exit_code())
+        }
+      };
+    }
+  };
+
+  match result {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(err) => {
+      app::print_startup_error(&err);
+      ExitCode::from(err.exit_code())
+    }
+  }
+}
+
+/// Builds the context for a management command: the migrations are left
+/// alone, whatever `database.migrations` says.
+async fn boot<H: Hooks>(environment: &Environment) -> Result<AppContext> {
+  let mut config: Config = H::load_config(environment).await?;
+  config.database.migrations = MigrationPolicy::Skip;
+  H::boot(config).await
+}
+
+/// Lists the routes without touching the database: they are built from a
+/// context whose connection is [`DatabaseConnection::Disconnected`].
+async fn routes<H: Hooks>(environment: &Environment) -> Result<()> {
+  let mut config = H::load_config(environment).await?;
+  config.database.replica = None;
+  let ctx = AppContext::builder()
+    .config(config)
+    .db(DatabaseConnection::Disconnected)
+    .build()
+    .await?;
+  let mut routes = H::routes(&ctx);
+  if !ctx.config.server.docs {
+    routes = routes.without_docs();
+  }
+  for route in routes.collect() {
+    let methods = if route.methods.is_empty() {
+      "*".to_string()
+    } else {
+      route
+        .methods
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+    };
+    let mut line = format!(
+      "{:<20} {:<30} {}",
+      methods.green(),
+      route.path,
+      format!("{:<16}", route.name).dimmed()
+    );
+    if let Some(auth) = &route.meta.auth {
+      line = format!("{line} {}", auth.to_string().yellow());
+    }
+    if route.meta.deprecated {
+      line = format!("{line} {}", "deprecated".red());
+    }
+    if let Some(summary) = &route.meta.summary {
+      line = format!("{line} {summary}");
+    }
+    println!("{line}");
+  }
+  Ok(())
+}
+
+async fn db<H: Hooks>(environment: &Environment, command: DbCommands) -> Result<()> {
+  let ctx = boot::<H>(environment).await?;
+  match command {
+    DbCommands::Migrate => {
+      Migrator::up(&ctx.db, None).await?;
+      println!("Migrations applied");
+    }
+    DbCommands::Status => {
+      for migration in Migrator::get_migration_with_status(&ctx.db).await? {
+        println!(
+          "{:<10} {}",
+          migration.status().to_string(),
+          migration.name()
+        );
+      }
+    }
+    DbCommands::Rollback { steps } => {
+      Migrator::down(&ctx.db, Some(steps)).await?;
+      println!("Reverted {steps} migration(s)");
+    }
+    DbCommands::Seed => {
+      H::seed(&ctx).await?;
+      println!("Seed data inserted");
+    }
+  }
+  ctx.db.close().await?;
+  Ok(())
+}
+
+async fn run_doctor(config: &Config) -> ExitCode {
+  let checks = doctor::run_all(config).await;
+  for check in &checks {
+    println!("{check}");
+  }
+  if checks.iter().all(doctor::Check::valid) {
+    ExitCode::SUCCESS
+  } else {
+    ExitCode::FAILURE
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routes_config::AppRoutes;
+  use async_trait::async_trait;
+  use clap::CommandFactory;
+
+  /// An application whose database cannot be opened.
+  struct Offline;
+
+  #[async_trait]
+  impl Hooks for Offline {
+    async fn load_config(environment: &Environment) -> Result<Config> {
+      let mut config = Config::for_environment(environment);
+      config.database.uri = Some("sqlite:///nonexistent/pos/db.sqlite".to_string());
+      Ok(config)
+    }
+
+    async fn boot(config: Config) -> Result<AppContext> {
+      AppContext::builder()
+        .config(config)
+        .db(DatabaseConnection::Disconnected)
+        .build()
+        .await
+    }
+
+    fn routes(_ctx: &AppContext) -> AppRoutes {
+      AppRoutes::with_default_routes()
+    }
+  }
+
+  #[tokio::test]
+  async fn lists_routes_without_the_database() {
+    assert!(routes::<Offline>(&Environment::Test).await.is_ok());
+  }
+
+  #[test]
+  fn cli_is_valid() {
+    Cli::command().debug_assert();
+  }
+
+  #[test]
+  fn can_parse_commands() {
+    let cli = Cli::try_parse_from(["bin", "db", "rollback", "--steps", "2", "-e", "test"]).unwrap();
+    assert_eq!(cli.environment, Environment::Test);
+    assert!(matches!(
+      cli.command,
+      Some(Commands::Db {
+        command: DbCommands::Rollback { steps: 2 }
+      })
+    ));
+
+    let cli = Cli::try_parse_from(["bin"]).unwrap();
+    assert!(cli.command.is_none());
+    assert_eq!(cli.environment, Environment::Development);
+  }
+}
@@ -0,0 +1,447 @@
+//! # API versions
+//!
+//! Groups of routes served under `/api/{version}`, such as `/api/v1/tasks`
+//! and `/api/v2/tasks`, so that older till frontends keep the payloads they
+//! were built for. A version can inherit the routes of an older one and only
+//! replace the handlers that change.
+//!
+//! Requests to an unversioned `/api/...` path go to the version named by the
+//! `Accept-Version` header, or to the default version: the last one added,
+//! unless set with [`AppRoutes::default_version`]. An unknown version is
+//! answered with `400 Bad Request`. The routes added with
+//! [`AppRoutes::add_route`] are matched first.
+//!
+//! The handlers of a deprecated version answer with the date it was
+//! deprecated in a `Deprecation` header (RFC 9745), and the date it goes
+//! away in a `Sunset` header (RFC 8594) once set.
+//!
+//! ```rust
+//! use pos_rust_local_backend::config::routing::get;
+//! use pos_rust_local_backend::config::api_version::ApiVersion;
+//! use pos_rust_local_backend::config::routes_config::{AppRoutes, Routes};
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! # async fn list_tasks() {}
+//! # async fn get_task() {}
+//! # async fn get_task_v2() {}
+//! let v1 = ApiVersion::new("v1").add_route(
+//!     Routes::at("/tasks")
+//!         .add("/", get(list_tasks))
+//!         .add("/{id}", get(get_task)),
+//! );
+//! // Same list, new payload for a single task
+//! let v2 = ApiVersion::new("v2")
+//!     .inherit(&v1)
+//!     .add_route(Routes::at("/tasks").add("/{id}", get(get_task_v2)));
+//!
+//! // Deprecated on 2026-04-01, removed on 2026-10-31
+//! let v1 = v1
+//!     .deprecated(UNIX_EPOCH + Duration::from_secs(1_775_001_600))
+//!     .sunset(UNIX_EPOCH + Duration::from_secs(1_793_404_800));
+//! let routes = AppRoutes::with_default_routes()
+//!     .add_version(v1)
+//!     .add_version(v2);
+//! ```
+#[cfg(doc)]
+use crate::config::routes_config::AppRoutes;
+use crate::config::routes_config::{join_path, Routes};
+use crate::errors::ErrorDetail;
+use crate::Error;
+use axum::extract::Request;
+use axum::http::header::VARY;
+use axum::http::uri::PathAndQuery;
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::{map_response, Next};
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Names the version an unversioned request is for.
+pub static ACCEPT_VERSION: HeaderName = HeaderName::from_static("accept-version");
+/// The date a version was deprecated, sent by its handlers.
+pub static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+/// The date a deprecated version goes away.
+pub static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Root of the versioned routes, under the [`AppRoutes`] prefix.
+const API_ROOT: &str = "/api";
+
+/// The routes of one API version, see the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct ApiVersion {
+  name: String,
+  routes: Vec<Routes>,
+  inherited: Vec<Routes>,
+  deprecated: Option<SystemTime>,
+  sunset: Option<SystemTime>,
+}
+
+impl ApiVersion {
+  /// Creates a version served under `/api/{name}`.
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.trim_matches('/').to_string(),
+      routes: Vec::new(),
+      inherited: Vec::new(),
+      deprecated: None,
+      sunset: None,
+    }
+  }
+
+  /// The name of the version, as in its path and the `Accept-Version`
+  /// header.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Adds routes to this version. A handler replaces an inherited one with
+  /// the same path when they answer a method in common.
+  ///
+  /// Handlers are replaced as a whole, so register the methods that change
+  /// separately from the others.
+  pub fn add_route(mut self, routes: Routes) -> Self {
+    self.routes.push(routes);
+    self
+  }
+
+  /// Starts from the routes of `older`, as they are now. Its deprecation is
+  /// not inherited.
+  pub fn inherit(mut self, older: &Self) -> Self {
+    self.inherited = older.own_routes();
+    self
+  }
+
+  /// Marks the version as deprecated since `date`: its handlers answer with
+  /// a `Deprecation: @<unix seconds>` header and show as deprecated in the
+  /// routes listing.
+  #[must_use]
+  pub fn deprecated(mut self, date: SystemTime) -> Self {
+    self.deprecated = Some(date);
+    self
+  }
+
+  /// Sets the date the version goes away, sent as an HTTP date such as
+  /// `Sat, 31 Oct 2026 00:00:00 GMT` in the `Sunset` header.
+  #[must_use]
+  pub fn sunset(mut self, date: SystemTime) -> Self {
+    self.sunset = Some(date);
+    self
+  }
+
+  /// The `Deprecation` and `Sunset` headers of the handlers.
+  fn deprecation_headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+    // Digits and HTTP dates only, always valid in a header
+    let value = |text: String| HeaderValue::try_from(text).expect("header value");
+    let deprecation = self.deprecated.map(|date| {
+      let seconds = date
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+      (DEPRECATION.clone(), value(format!("@{seconds}")))
+    });
+    let sunset = self
+      .sunset
+      .map(|date| (SUNSET.clone(), value(httpdate::fmt_http_date(date))));
+    deprecation.into_iter().chain(sunset).collect()
+  }
+
+  /// The inherited routes that were not replaced, then the routes of this
+  /// version, relative to `/api/{name}`.
+  fn own_routes(&self) -> Vec<Routes> {
+    let replacements: Vec<(String, Vec<Method>)> = self
+      .routes
+      .iter()
+      .flat_map(|routes| {
+        let prefix = routes.prefix.clone().unwrap_or_default();
+        routes
+          .handlers
+          .iter()
+          .map(move |handler| (join_path(&[&prefix, &handler.uri]), handler.actions.clone()))
+      })
+      .collect();
+    let replaced = |path: &str, methods: &[Method]| {
+      replacements.iter().any(|(other_path, other_methods)| {
+        other_path == path
+          && (methods.is_empty()
+            || other_methods.is_empty()
+            || methods.iter().any(|method| other_methods.contains(method)))
+      })
+    };
+
+    let inherited = self.inherited.iter().cloned().filter_map(|mut routes| {
+      let prefix = routes.prefix.clone().unwrap_or_default();
+      routes
+        .handlers
+        .retain(|handler| !replaced(&join_path(&[&prefix, &handler.uri]), &handler.actions));
+      (!routes.handlers.is_empty()).then_some(routes)
+    });
+    inherited.chain(self.routes.iter().cloned()).collect()
+  }
+
+  /// The routes to mount: under `/api/{name}`, with names prefixed by the
+  /// version and the deprecation headers.
+  pub(crate) fn mounted_routes(&self) -> Vec<Routes> {
+    let root = join_path(&[API_ROOT, &self.name]);
+    self
+      .own_routes()
+      .into_iter()
+      .map(|mut routes| {
+        routes.prefix = Some(join_path(&[
+          &root,
+          routes.prefix.as_deref().unwrap_or_default(),
+        ]));
+        for handler in &mut routes.handlers {
+          if let Some(name) = &mut handler.meta.name {
+            *name = format!("{}_{name}", self.name);
+          }
+          handler.meta.deprecated |= self.deprecated.is_some();
+        }
+        let headers = Arc::new(self.deprecation_headers());
+        if !headers.is_empty() {
+          routes = routes.layer(map_response(move |mut response: Response| {
+            let headers = headers.clone();
+            async move {
+              for (name, value) in headers.iter() {
+                response.headers_mut().insert(name.clone(), value.clone());
+              }
+              response
+            }
+          }));
+        }
+        routes
+      })
+      .collect()
+  }
+}
+
+/// Sends unversioned `/api/...` requests to a version, see the
+/// [module documentation](self).
+#[derive(Debug)]
+pub(crate) struct VersionSelector {
+  /// `/api` under the prefix of the app.
+  root: String,
+  versions: Vec<String>,
+  default: String,
+}
+
+impl VersionSelector {
+  /// Returns `None` without versions.
+  pub(crate) fn new(prefix: &str, versions: &[ApiVersion], default: Option<&str>) -> Option<Self> {
+    let default = default
+      .map(ToString::to_string)
+      .or_else(|| versions.last().map(|version| version.name.clone()))?;
+    Some(Self {
+      root: join_path(&[prefix, API_ROOT]),
+      versions: versions
+        .iter()
+        .map(|version| version.name.clone())
+        .collect(),
+      default,
+    })
+  }
+
+  /// The path to serve `request` from, `None` when it is not an unversioned
+  /// API path.
+  fn versioned_path(&self, request: &Request) -> Result<Option<String>, Error> {
+    let Some(rest) = request.uri().path().strip_prefix(&self.root) else {
+      return Ok(None);
+    };
+    if !(rest.is_empty() || rest.starts_with('/')) {
+      return Ok(None);
+    }
+    let first = rest.trim_start_matches('/').split('/').next().unwrap_or("");
+    if self.versions.iter().any(|version| version == first) {
+      return Ok(None);
+    }
+
+    let version = match request.headers().get(&ACCEPT_VERSION) {
+      Some(header) => {
+        let requested = header.to_str().unwrap_or_default().trim();
+        self
+          .versions
+          .iter()
+          .find(|version| *version == requested)
+          .ok_or_else(|| {
+            Error::CustomError(
+              StatusCode::BAD_REQUEST,
+              ErrorDetail::new(
+                "unsupported_version".to_string(),
+                format!(
+                  "API version `{requested}` is not served, use one of: {}",
+                  self.versions.join(", ")
+                ),
+              ),
+            )
+          })?
+      }
+      None => &self.default,
+    };
+    Ok(Some(format!("{}/{version}{rest}", self.root)))
+  }
+}
+
+/// Rewrites unversioned requests before they are routed.
+pub(crate) async fn select_version(
+  selector: Arc<VersionSelector>,
+  mut request: Request,
+  next: Next,
+) -> Response {
+  let path = match selector.versioned_path(&request) {
+    Ok(Some(path)) => path,
+    Ok(None) => return next.run(request).await,
+    Err(err) => return err.into_response(),
+  };
+  let path_and_query = match request.uri().query() {
+    Some(query) => format!("{path}?{query}"),
+    None => path,
+  };
+  let mut parts = request.uri().clone().into_parts();
+  parts.path_and_query = PathAndQuery::try_from(path_and_query).ok();
+  if let Ok(uri) = Uri::from_parts(parts) {
+    *request.uri_mut() = uri;
+  }
+
+  let mut response = next.run(request).await;
+  response
+    .headers_mut()
+    .append(VARY, HeaderValue::from_static("accept-version"));
+  response
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routes_config::AppRoutes;
+  use crate::config::routing::get;
+  use crate::config::testing::offline_context;
+  use crate::config::Config;
+  use axum::body::Body;
+  use axum::http::header::HeaderMap;
+  use http_body_util::BodyExt;
+  use std::time::Duration;
+  use tower::ServiceExt;
+
+  fn versions() -> AppRoutes {
+    let v1 = ApiVersion::new("v1").add_route(
+      Routes::at("/tasks")
+        .add("/", get(|| async { "v1 list" }))
+        .name("list_tasks")
+        .add("/{id}", get(|| async { "v1 task" }))
+        .name("get_task"),
+    );
+    let v2 = ApiVersion::new("v2")
+      .inherit(&v1)
+      .add_route(Routes::at("/tasks").add("/{id}", get(|| async { "v2 task" })));
+    let v1 = v1
+      .deprecated(UNIX_EPOCH + Duration::from_secs(1_775_001_600))
+      .sunset(UNIX_EPOCH + Duration::from_secs(1_793_404_800));
+    AppRoutes::with_default_routes()
+      .add_version(v1)
+      .add_version(v2)
+  }
+
+  async fn call(
+    routes: AppRoutes,
+    uri: &str,
+    version: Option<&str>,
+  ) -> (StatusCode, HeaderMap, String) {
+    let ctx = offline_context(Config::default()).await;
+    let mut request = Request::builder().uri(uri);
+    if let Some(version) = version {
+      request = request.header(&ACCEPT_VERSION, version);
+    }
+    let response = routes
+      .into_router(&ctx)
+      .unwrap()
+      .oneshot(request.body(Body::empty()).unwrap())
+      .await
+      .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, String::from_utf8_lossy(&body).to_string())
+  }
+
+  #[test]
+  fn newer_versions_inherit_and_override_routes() {
+    let routes: Vec<_> = versions()
+      .collect()
+      .into_iter()
+      .filter(|route| route.path.starts_with("/api/v"))
+      .map(|route| (route.path, route.name, route.meta.deprecated))
+      .collect();
+    assert_eq!(
+      routes,
+      [
+        (
+          "/api/v1/tasks".to_string(),
+          "v1_list_tasks".to_string(),
+          true
+        ),
+        (
+          "/api/v1/tasks/{id}".to_string(),
+          "v1_get_task".to_string(),
+          true
+        ),
+        (
+          "/api/v2/tasks".to_string(),
+          "v2_list_tasks".to_string(),
+          false
+        ),
+        (
+          "/api/v2/tasks/{id}".to_string(),
+          "api_v2_tasks_id".to_string(),
+          false
+        ),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn serves_each_version_under_its_path() {
+    let (_, headers, body) = call(versions(), "/api/v1/tasks/1", None).await;
+    assert_eq!(body, "v1 task");
+    assert_eq!(headers[&DEPRECATION], "@1775001600");
+    assert_eq!(headers[&SUNSET], "Sat, 31 Oct 2026 00:00:00 GMT");
+
+    let (_, headers, body) = call(versions(), "/api/v2/tasks", None).await;
+    assert_eq!(body, "v1 list");
+    assert!(!headers.contains_key(&DEPRECATION));
+    let (_, _, body) = call(versions(), "/api/v2/tasks/1", None).await;
+    assert_eq!(body, "v2 task");
+  }
+
+  #[tokio::test]
+  async fn unversioned_requests_follow_the_accept_version_header() {
+    let (_, headers, body) = call(versions(), "/api/tasks/1?full=true", None).await;
+    assert_eq!(body, "v2 task");
+    assert_eq!(headers[VARY], "accept-version");
+
+    let (_, headers, body) = call(versions(), "/api/tasks/1", Some("v1")).await;
+    assert_eq!(body, "v1 task");
+    assert_eq!(headers[&DEPRECATION], "@1775001600");
+
+    let routes = versions().default_version("v1");
+    let (_, _, body) = call(routes, "/api/tasks/1", None).await;
+    assert_eq!(body, "v1 task");
+
+    let (status, _, body) = call(versions(), "/api/tasks/1", Some("v9")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("use one of: v1, v2"), "{body}");
+
+    let (status, headers, _) = call(versions(), "/_health", Some("v9")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!headers.contains_key(VARY));
+  }
+
+  #[tokio::test]
+  async fn unversioned_routes_are_matched_before_the_versions() {
+    let routes =
+      || versions().add_route(Routes::at("/api/tasks").add("/", get(|| async { "unversioned" })));
+    let (_, headers, body) = call(routes(), "/api/tasks", Some("v1")).await;
+    assert_eq!(body, "unversioned");
+    assert!(!headers.contains_key(VARY));
+
+    let (_, _, body) = call(routes(), "/api/tasks/1", None).await;
+    assert_eq!(body, "v2 task");
+  }
+}
@@ -1,31 +1,223 @@
 use crate::cache;
+use crate::cache::drivers::CacheDriver;
 use crate::cache::Cache;
-use sea_orm::{Database, DatabaseConnection};
+use crate::config::extensions::Extensions;
+use crate::config::{db, Config, Environment};
+use crate::shutdown::Shutdown;
+use crate::Result;
+use sea_orm::DatabaseConnection;
 use std::sync::Arc;
-use crate::config::Config;
+
+/// Connection string of the database created by [`AppContextBuilder`] when
+/// none is configured.
+pub const IN_MEMORY_SQLITE: &str = "sqlite::memory:";
 
 #[derive(Clone)]
 pub struct AppContext {
+  /// The main pool, for writes and reads that must see them.
   pub db: DatabaseConnection,
+  /// The read-only pool of `database.replica`, prefer [`Self::read_db`].
+  pub replica: Option<DatabaseConnection>,
   pub cache: Arc<Cache>,
   pub config: Config,
+  /// Subsystems registered at startup, see [`crate::config::extensions`].
+  pub extensions: Extensions,
+  /// Shutdown hooks and in-flight requests, see [`crate::shutdown`].
+  pub shutdown: Shutdown,
 }
 
 impl AppContext {
-  pub fn new(db: DatabaseConnection, cache: Arc<Cache>) -> Self {
+  pub fn new(db: DatabaseConnection, cache: Arc<Cache>, config: Config) -> Self {
     Self {
       db,
+      replica: None,
       cache,
-      config: Config::new(),
+      config,
+      extensions: Extensions::new(),
+      shutdown: Shutdown::new(),
     }
   }
+
+  /// The connection for read-only queries such as reports: the replica when
+  /// configured, so they never hold up writes, the main pool otherwise.
+  #[must_use]
+  pub fn read_db(&self) -> &DatabaseConnection {
+    self.replica.as_ref().unwrap_or(&self.db)
+  }
+
+  /// Starts building an [`AppContext`], by default with the `test`
+  /// environment, a migrated in-memory SQLite database and the configured
+  /// cache.
+  ///
+  /// # Example
+  /// ```
+  /// use pos_rust_local_backend::cache;
+  /// use pos_rust_local_backend::config::app_context::AppContext;
+  ///
+  /// # #[tokio::main]
+  /// # async fn main() -> pos_rust_local_backend::Result<()> {
+  /// let ctx = AppContext::builder()
+  ///   .cache(cache::drivers::null::new())
+  ///   .build()
+  ///   .await?;
+  /// assert_eq!(ctx.cache.get("key").await?, None);
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[must_use]
+  pub fn builder() -> AppContextBuilder {
+    AppContextBuilder::default()
+  }
 }
 
+/// Builds an [`AppContext`] for tests and examples.
+#[derive(Default)]
+pub struct AppContextBuilder {
+  config: Option<Config>,
+  db: Option<DatabaseConnection>,
+  replica: Option<DatabaseConnection>,
+  cache: Option<Box<dyn CacheDriver>>,
+  extensions: Extensions,
+}
+
+impl AppContextBuilder {
+  /// Uses the given configuration instead of the `test` environment
+  /// defaults. An in-memory SQLite database is still used unless
+  /// `database.uri` is set.
+  #[must_use]
+  pub fn config(mut self, config: Config) -> Self {
+    self.config = Some(config);
+    self
+  }
+
+  /// Uses an existing connection as is, skipping PRAGMAs and migrations.
+  #[must_use]
+  pub fn db(mut self, db: DatabaseConnection) -> Self {
+    self.db = Some(db);
+    self
+  }
+
+  /// Uses an existing connection as the read-only pool, instead of
+  /// `database.replica`.
+  #[must_use]
+  pub fn replica(mut self, db: DatabaseConnection) -> Self {
+    self.replica = Some(db);
+    self
+  }
+
+  /// Uses the given cache driver instead of the configured one.
+  #[must_use]
+  pub fn cache(mut self, driver: Box<dyn CacheDriver>) -> Self {
+    self.cache = Some(driver);
+    self
+  }
+
+  /// Registers an extension, see [`Extensions::insert`].
+  #[must_use]
+  pub fn extension<T: Send + Sync + 'static>(mut self, value: T) -> Self {
+    self.extensions.insert(value);
+    self
+  }
+
+  /// Connects the database and builds the [`AppContext`]. Without a
+  /// connection nor `database.uri`, the database is an in-memory SQLite one.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error when the database cannot be opened or migrated, or an
+  /// [`Error::Startup`] on `database.uri` when none is set without the
+  /// `db-sqlite` feature.
+  pub async fn build(self) -> Result<AppContext> {
+    let mut config = self
+      .config
+      .unwrap_or_else(|| Config::for_environment(&Environment::Test));
 
+    let db = match self.db {
+      Some(db) => db,
+      None => {
+        if config.database.uri.is_none() {
+          in_memory_database(&mut config)?;
+        }
+        db::connect(&config).await?
+      }
+    };
+
+    let replica = match self.replica {
+      Some(replica) => Some(replica),
+      None => db::connect_replica(&config).await?,
+    };
+
+    let driver = self
+      .cache
+      .unwrap_or_else(|| cache::drivers::from_config(&config.cache));
+
+    let mut ctx = AppContext::new(db, Cache::new(driver).into(), config);
+    ctx.replica = replica;
+    ctx.extensions = self.extensions;
+    Ok(ctx)
+  }
+}
+
+/// Points `config` to an in-memory SQLite database, the default of
+/// [`AppContextBuilder::build`].
+#[cfg(feature = "db-sqlite")]
+fn in_memory_database(config: &mut Config) -> Result<()> {
+  // Every connection to `sqlite::memory:` opens its own database, so the
+  // pool is limited to a single connection
+  config.database.uri = Some(IN_MEMORY_SQLITE.to_string());
+  config.database.min_connections = 1;
+  config.database.max_connections = 1;
+  Ok(())
+}
+
+#[cfg(not(feature = "db-sqlite"))]
+fn in_memory_database(_config: &mut Config) -> Result<()> {
+  use crate::errors::StartupErrorKind;
+  use crate::Error;
+
+  Err(Error::startup(
+    StartupErrorKind::Config,
+    "database.uri",
+    Error::Message(
+      "not set, and there is no in-memory default without the `db-sqlite` feature: set it, or \
+       pass a connection with `AppContextBuilder::db`"
+        .to_string(),
+    ),
+  ))
+}
+
+/// Builds an [`AppContext`] with the [`AppContextBuilder`] defaults.
+///
+/// # Panics
+///
+/// Panics when the in-memory database cannot be created, or without the
+/// `db-sqlite` feature, which makes it suitable for tests and examples only.
 pub async fn get_app_context() -> AppContext {
-  let db = Database::connect("db_url").await.unwrap();
-  AppContext::new(
-    db,
-    Cache::new(cache::drivers::inmem::new()).into()
-  )
+  AppContext::builder()
+    .build()
+    .await
+    .expect("Failed to build the app context")
+}
+
+#[cfg(all(test, feature = "db-sqlite"))]
+mod tests {
+  use super::*;
+  use crate::entity::prelude::Task;
+  use sea_orm::{Database, EntityTrait};
+
+  #[tokio::test]
+  async fn reads_go_to_the_replica_when_set() {
+    let ctx = get_app_context().await;
+    assert!(Task::find().all(ctx.read_db()).await.is_ok());
+
+    // Not migrated, so reads fail when they reach it
+    let replica = Database::connect(IN_MEMORY_SQLITE).await.unwrap();
+    let ctx = AppContext::builder()
+      .replica(replica)
+      .build()
+      .await
+      .unwrap();
+    assert!(Task::find().all(ctx.read_db()).await.is_err());
+    assert!(Task::find().all(&ctx.db).await.is_ok());
+  }
 }
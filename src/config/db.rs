@@ -1,45 +1,518 @@
 use crate::cache;
 use crate::cache::Cache;
 use crate::config::app_context::AppContext;
-use dotenvy::dotenv;
+use crate::config::{Config, Database as DatabaseConfig, MigrationPolicy};
+use crate::errors::StartupErrorKind;
+use crate::{Error, Result};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
 use migration::{Migrator, MigratorTrait};
-use sea_orm::{ConnectOptions, ConnectionTrait, Database, DbErr};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection, DbErr, RuntimeErr};
 use std::env;
+use std::time::Duration;
 
-pub async fn db_connection() -> Result<AppContext, DbErr> {
-  dotenv().ok();
+/// Connects to the database, applies the PRAGMAs and migrations, and builds
+/// the [`AppContext`].
+///
+/// # Errors
+///
+/// Returns an [`Error::Startup`] naming the setting at fault when the
+/// database cannot be reached, is locked by another process, or has
+/// migrations that cannot be applied.
+pub async fn db_connection(config: Config) -> Result<AppContext> {
+  let db = connect(&config).await?;
+  let replica = connect_replica(&config).await?;
+  let cache = Cache::new(cache::drivers::from_config(&config.cache));
+  let mut ctx = AppContext::new(db, cache.into(), config);
+  ctx.replica = replica;
+  Ok(ctx)
+}
+
+/// Opens the connection pool described by the configuration, then applies
+/// the PRAGMAs and the migration policy.
+///
+/// # Errors
+///
+/// See [`db_connection`].
+pub async fn connect(config: &Config) -> Result<DatabaseConnection> {
+  let db_url = match config.database.uri.clone() {
+    Some(uri) => uri,
+    None => env::var("DATABASE_URL").map_err(|err| {
+      Error::startup(
+        StartupErrorKind::Config,
+        "database.uri",
+        Error::Message(format!("not set, and DATABASE_URL is unusable: {err}")),
+      )
+    })?,
+  };
+  let opt = connect_options(
+    db_url,
+    &config.database,
+    config.database.min_connections,
+    config.database.max_connections,
+  );
+
+  let pragmas = config.pragmas.entries();
+  check_pragmas(&pragmas, config.database.max_connections)?;
+  let db = open(opt, &pragmas, "database.uri").await?;
+
+  // Handle migrations according to the environment's policy
+  match config.database.migrations {
+    MigrationPolicy::Up => Migrator::up(&db, None)
+      .await
+      .map_err(|err| db_error(StartupErrorKind::Migration, "database.migrations", err))?,
+    MigrationPolicy::Check => {
+      let pending = Migrator::get_pending_migrations(&db)
+        .await
+        .map_err(|err| db_error(StartupErrorKind::Migration, "database.migrations", err))?;
+      if !pending.is_empty() {
+        return Err(Error::startup(
+          StartupErrorKind::Migration,
+          "database.migrations",
+          Error::Message(format!(
+            "{} pending migration(s) in the `{}` environment, run them before starting",
+            pending.len(),
+            config.environment
+          )),
+        ));
+      }
+    }
+    MigrationPolicy::Skip => {}
+  }
+
+  Ok(db)
+}
 
-  let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-  let mut opt = ConnectOptions::new(db_url.to_owned());
+/// Opens the read-only pool of `database.replica`, if configured.
+///
+/// Migrations and the PRAGMAs that change the database file (`journal_mode`
+/// and `locking_mode`) are left to the main pool; the others, `busy_timeout`
+/// included, apply to the replica too.
+///
+/// # Errors
+///
+/// Returns an [`Error::Startup`] when the replica cannot be reached, or is a
+/// SQLite file the main pool locks with `locking_mode = EXCLUSIVE`.
+pub async fn connect_replica(config: &Config) -> Result<Option<DatabaseConnection>> {
+  let Some(replica) = &config.database.replica else {
+    return Ok(None);
+  };
+  let exclusive = config
+    .pragmas
+    .locking_mode
+    .as_deref()
+    .is_some_and(|mode| mode.eq_ignore_ascii_case("exclusive"));
+  if exclusive && replica.uri.starts_with("sqlite:") {
+    return Err(Error::startup(
+      StartupErrorKind::Config,
+      "database.replica.uri",
+      Error::Message(
+        "a SQLite replica cannot read the database while `pragmas.locking_mode = EXCLUSIVE` \
+         locks it, use NORMAL"
+          .to_string(),
+      ),
+    ));
+  }
+  let opt = connect_options(
+    replica.uri.clone(),
+    &config.database,
+    replica.min_connections,
+    replica.max_connections,
+  );
+  let pragmas: Vec<_> = config
+    .pragmas
+    .entries()
+    .into_iter()
+    .filter(|(name, _)| !matches!(*name, "journal_mode" | "locking_mode"))
+    .collect();
+  check_pragmas(&pragmas, replica.max_connections)?;
+  open(opt, &pragmas, "database.replica.uri").await.map(Some)
+}
+
+fn connect_options(
+  uri: String,
+  database: &DatabaseConfig,
+  min_connections: u32,
+  max_connections: u32,
+) -> ConnectOptions {
+  let mut opt = ConnectOptions::new(uri);
+  opt
+    .sqlx_logging(database.enable_logging)
+    .sqlx_logging_level(log::LevelFilter::Trace) // Enable Debug level for SQLx
+    .max_connections(max_connections) // Optimize connection pool
+    .min_connections(min_connections) // Minimum connections to maintain
+    .connect_timeout(Duration::from_millis(database.connect_timeout)) // Connection timeout
+    .acquire_timeout(Duration::from_millis(database.acquire_timeout)) // Acquire timeout
+    .idle_timeout(Duration::from_millis(database.idle_timeout)); // Idle timeout
   opt
-      .sqlx_logging(true) // Disable SQLx logging
-      .sqlx_logging_level(log::LevelFilter::Trace) // Enable Debug level for SQLx
-      .max_connections(10) // Optimize connection pool
-      .min_connections(2)  // Minimum connections to maintain
-      .connect_timeout(std::time::Duration::from_secs(30)) // Connection timeout
-      .acquire_timeout(std::time::Duration::from_secs(30)) // Acquire timeout
-      .idle_timeout(std::time::Duration::from_secs(600)); // Idle timeout
-
-  let db = Database::connect(opt).await?;
-
-  // Apply PRAGMA settings for optimization
-  db.execute_unprepared("PRAGMA journal_mode=WAL2;").await?;
-  db.execute_unprepared("PRAGMA synchronous=NORMAL;").await?;
-  db.execute_unprepared("PRAGMA temp_store=MEMORY;").await?;
-  db.execute_unprepared("PRAGMA cache_size=-20000;").await?; // Negative value sets size in KB
-  db.execute_unprepared("PRAGMA locking_mode=EXCLUSIVE;").await?;
-  db.execute_unprepared("PRAGMA foreign_keys=ON;").await?; // Ensure foreign key checks
-  db.execute_unprepared("PRAGMA busy_timeout=5000;").await?; // Ensure foreign key checks
-
-
-  // Run migrations
-  Migrator::up(&db, None)
+}
+
+/// Opens the pool, running the PRAGMAs on every SQLite connection it opens
+/// and checking that SQLite kept them.
+async fn open(
+  opt: ConnectOptions,
+  pragmas: &[(&'static str, String)],
+  setting: &str,
+) -> Result<DatabaseConnection> {
+  #[cfg(feature = "db-sqlite")]
+  if opt.get_url().starts_with("sqlite:") {
+    let db = sqlite::open(opt.clone(), pragmas)
+      .await
+      .map_err(|err| db_error(StartupErrorKind::DatabaseUnavailable, setting, err))?;
+    sqlite::verify_pragmas(&db, opt.get_url(), pragmas).await?;
+    return Ok(db);
+  }
+  #[cfg(not(feature = "db-sqlite"))]
+  let _ = pragmas;
+
+  Database::connect(opt)
     .await
-    .expect("Failed to run migrations");
+    .map_err(|err| db_error(StartupErrorKind::DatabaseUnavailable, setting, err))
+}
+
+#[cfg(feature = "db-sqlite")]
+mod sqlite {
+  use super::db_error;
+  use crate::errors::StartupErrorKind;
+  use crate::{Error, Result};
+  use sea_orm::sqlx::sqlite::{Sqlite, SqliteConnectOptions};
+  use sea_orm::sqlx::ConnectOptions as _;
+  use sea_orm::{
+    ConnectOptions, ConnectionTrait, DatabaseConnection, DbBackend, DbErr, RuntimeErr, Statement,
+  };
+  use std::time::Duration;
+
+  /// Builds the sqlx pool directly, as sea-orm has no way to run statements
+  /// when the pool opens a connection.
+  pub(super) async fn open(
+    opt: ConnectOptions,
+    pragmas: &[(&'static str, String)],
+  ) -> std::result::Result<DatabaseConnection, DbErr> {
+    let conn_err = |err| DbErr::Conn(RuntimeErr::SqlxError(err));
+    let mut options: SqliteConnectOptions = opt.get_url().parse().map_err(conn_err)?;
+    for (name, value) in pragmas {
+      options = match (*name, value.parse::<u64>()) {
+        // Not a PRAGMA for sqlx, which sets it through the C API
+        ("busy_timeout", Ok(millis)) => options.busy_timeout(Duration::from_millis(millis)),
+        _ => options.pragma(*name, value.clone()),
+      };
+    }
+    options = if opt.get_sqlx_logging() {
+      options.log_statements(opt.get_sqlx_logging_level())
+    } else {
+      options.disable_statement_logging()
+    };
+
+    let pool = opt
+      .sqlx_pool_options::<Sqlite>()
+      .connect_with(options)
+      .await
+      .map_err(conn_err)?;
+    Ok(pool.into())
+  }
+
+  /// Reads every PRAGMA back and fails on the first one SQLite did not keep,
+  /// as SQLite ignores unknown values without an error.
+  pub(super) async fn verify_pragmas(
+    db: &DatabaseConnection,
+    url: &str,
+    pragmas: &[(&'static str, String)],
+  ) -> Result<()> {
+    for (name, value) in pragmas {
+      let setting = format!("pragmas.{name}");
+      let statement = Statement::from_string(DbBackend::Sqlite, format!("PRAGMA {name};"));
+      let row = db
+        .query_one(statement)
+        .await
+        .map_err(|err| db_error(StartupErrorKind::Config, &setting, err))?;
+      let actual = row
+        .and_then(|row| {
+          row
+            .try_get_by_index::<i64>(0)
+            .map(|v| v.to_string())
+            .or_else(|_| row.try_get_by_index::<String>(0))
+            .ok()
+        })
+        .unwrap_or_default()
+        .to_lowercase();
+      let expected = expected_pragma(name, value);
+      // In-memory databases only have the `memory` journal
+      let accepted =
+        actual == expected || (*name == "journal_mode" && actual == "memory" && is_in_memory(url));
+      if !accepted {
+        let hint = if *name == "journal_mode" {
+          " (stock SQLite supports DELETE, TRUNCATE, PERSIST, MEMORY, WAL and OFF)"
+        } else {
+          ""
+        };
+        return Err(Error::startup(
+          StartupErrorKind::Config,
+          &setting,
+          Error::Message(format!(
+            "set to `{value}`, but SQLite reports `{actual}`{hint}"
+          )),
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  /// Whether `url` opens an in-memory database, see
+  /// <https://www.sqlite.org/inmemorydb.html>.
+  pub(super) fn is_in_memory(url: &str) -> bool {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    path.ends_with(":memory:") || query.split('&').any(|param| param == "mode=memory")
+  }
+
+  /// The value SQLite reports for a PRAGMA set to `value`: keywords read back
+  /// as their number, modes in lowercase.
+  pub(super) fn expected_pragma(name: &str, value: &str) -> String {
+    let value = value.to_lowercase();
+    let keywords: &[&str] = match name {
+      "synchronous" => &["off", "normal", "full", "extra"],
+      "temp_store" => &["default", "file", "memory"],
+      "foreign_keys" => &["off", "on"],
+      _ => &[],
+    };
+    keywords
+      .iter()
+      .position(|keyword| *keyword == value)
+      .map_or(value, |index| index.to_string())
+  }
+}
+
+/// Rejects values that are not a plain keyword or number, as sqlx sends them
+/// unquoted, and `EXCLUSIVE` locking on a pool, which makes its own
+/// connections wait for each other.
+fn check_pragmas(pragmas: &[(&'static str, String)], max_connections: u32) -> Result<()> {
+  for (name, value) in pragmas {
+    let plain = !value.is_empty()
+      && value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !plain {
+      return Err(Error::startup(
+        StartupErrorKind::Config,
+        format!("pragmas.{name}"),
+        Error::Message(format!("`{value}` is not a keyword or a number")),
+      ));
+    }
+  }
+
+  let exclusive = pragmas
+    .iter()
+    .any(|(name, value)| *name == "locking_mode" && value.eq_ignore_ascii_case("exclusive"));
+  if exclusive && max_connections > 1 {
+    return Err(Error::startup(
+      StartupErrorKind::Config,
+      "pragmas.locking_mode",
+      Error::Message(format!(
+        "EXCLUSIVE needs `database.max_connections = 1`, got {max_connections}; \
+         it also keeps the `migration` CLI and backup tools out while the server runs"
+      )),
+    ));
+  }
+  Ok(())
+}
+
+/// Extracts the connection for read-only queries: the replica when
+/// configured, the main pool otherwise. See [`AppContext::read_db`].
+///
+/// ```rust
+/// use pos_rust_local_backend::config::db::ReadDb;
+/// use pos_rust_local_backend::entity::prelude::Task;
+/// use sea_orm::{EntityTrait, PaginatorTrait};
+///
+/// async fn count_tasks(ReadDb(db): ReadDb) -> pos_rust_local_backend::Result<String> {
+///     Ok(Task::find().count(&db).await?.to_string())
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ReadDb(pub DatabaseConnection);
+
+impl FromRequestParts<AppContext> for ReadDb {
+  type Rejection = std::convert::Infallible;
+
+  async fn from_request_parts(
+    _parts: &mut Parts,
+    ctx: &AppContext,
+  ) -> std::result::Result<Self, Self::Rejection> {
+    Ok(Self(ctx.read_db().clone()))
+  }
+}
+
+/// Wraps a database error as a startup failure, reporting a lock held by
+/// another process as [`StartupErrorKind::DatabaseLocked`] whatever the step.
+fn db_error(kind: StartupErrorKind, setting: &str, err: DbErr) -> Error {
+  let kind = if is_locked(&err) {
+    StartupErrorKind::DatabaseLocked
+  } else {
+    kind
+  };
+  Error::startup(kind, setting, err)
+}
+
+/// Whether the error is SQLite's `SQLITE_BUSY` or `SQLITE_LOCKED`.
+fn is_locked(err: &DbErr) -> bool {
+  let (DbErr::Conn(runtime) | DbErr::Exec(runtime) | DbErr::Query(runtime)) = err else {
+    // Migration errors only carry the message
+    return err.to_string().contains("database is locked");
+  };
+  match runtime {
+    RuntimeErr::SqlxError(sea_orm::SqlxError::Database(db_err)) => is_sqlite_lock(db_err.as_ref()),
+    RuntimeErr::SqlxError(_) => false,
+    RuntimeErr::Internal(message) => message.contains("database is locked"),
+  }
+}
+
+#[cfg(feature = "db-sqlite")]
+fn is_sqlite_lock(db_err: &dyn sea_orm::sqlx::error::DatabaseError) -> bool {
+  const SQLITE_BUSY: i32 = 5;
+  const SQLITE_LOCKED: i32 = 6;
+
+  if db_err
+    .try_downcast_ref::<sea_orm::SqlxSqliteError>()
+    .is_none()
+  {
+    return false;
+  }
+  db_err
+    .code()
+    .and_then(|code| code.parse::<i32>().ok())
+    // Extended result codes keep the primary code in the low byte
+    .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+}
+
+#[cfg(not(feature = "db-sqlite"))]
+fn is_sqlite_lock(_db_err: &dyn sea_orm::sqlx::error::DatabaseError) -> bool {
+  false
+}
+
+#[cfg(all(test, feature = "db-sqlite"))]
+mod tests {
+  use super::sqlite::{expected_pragma, is_in_memory, verify_pragmas};
+  use super::*;
+  use crate::config::Environment;
+  use sea_orm::{ConnectionTrait, DbBackend, Statement, TransactionTrait};
+
+  /// The configuration of a database file in `dir`, removed on drop.
+  fn file_config(dir: &tempfile::TempDir) -> Config {
+    let path = dir.path().join("db.sqlite");
+    let mut config = Config::for_environment(&Environment::Test);
+    config.database.uri = Some(format!("sqlite://{}?mode=rwc", path.display()));
+    config.database.min_connections = 2;
+    config.database.max_connections = 2;
+    config
+  }
+
+  async fn pragma(db: &impl ConnectionTrait, name: &str) -> i64 {
+    let statement = Statement::from_string(DbBackend::Sqlite, format!("PRAGMA {name};"));
+    let row = db.query_one(statement).await.unwrap().unwrap();
+    row.try_get_by_index(0).unwrap()
+  }
+
+  #[test]
+  fn maps_keywords_to_read_back_values() {
+    assert_eq!(expected_pragma("synchronous", "NORMAL"), "1");
+    assert_eq!(expected_pragma("temp_store", "memory"), "2");
+    assert_eq!(expected_pragma("foreign_keys", "ON"), "1");
+    assert_eq!(expected_pragma("journal_mode", "WAL"), "wal");
+    assert_eq!(expected_pragma("cache_size", "-20000"), "-20000");
+  }
+
+  #[test]
+  fn recognizes_in_memory_databases() {
+    assert!(is_in_memory("sqlite::memory:"));
+    assert!(is_in_memory("sqlite://pos?mode=memory&cache=shared"));
+    assert!(!is_in_memory("sqlite://pos.sqlite?mode=rwc"));
+  }
+
+  #[tokio::test]
+  async fn only_in_memory_databases_may_keep_the_memory_journal() {
+    let wal = [("journal_mode", "WAL".to_string())];
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    assert!(verify_pragmas(&db, "sqlite::memory:", &wal).await.is_ok());
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = file_config(&dir).database.uri.unwrap();
+    let mut opt = ConnectOptions::new(&url);
+    opt.max_connections(1);
+    let db = Database::connect(opt).await.unwrap();
+    db.execute_unprepared("PRAGMA journal_mode = MEMORY;")
+      .await
+      .unwrap();
+    let err = verify_pragmas(&db, &url, &wal).await.unwrap_err();
+    assert!(matches!(
+      err,
+      Error::Startup { ref setting, .. } if setting == "pragmas.journal_mode"
+    ));
+  }
+
+  #[tokio::test]
+  async fn pragmas_apply_to_every_connection() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = file_config(&dir);
+    config.pragmas.cache_size = Some(-1234);
+    let db = connect(&config).await.unwrap();
+
+    // The transaction holds one connection, so the pool opens the other
+    let txn = db.begin().await.unwrap();
+    assert_eq!(pragma(&txn, "cache_size").await, -1234);
+    assert_eq!(pragma(&db, "cache_size").await, -1234);
+    txn.commit().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn sqlite_replicas_read_the_main_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = file_config(&dir);
+    let uri = config.database.uri.clone().unwrap();
+    config.database.replica = Some(crate::config::Replica {
+      uri: uri.replace("mode=rwc", "mode=ro"),
+      min_connections: 1,
+      max_connections: 2,
+    });
+    let db = connect(&config).await.unwrap();
+    let replica = connect_replica(&config).await.unwrap().unwrap();
+    assert_eq!(pragma(&replica, "busy_timeout").await, 5_000);
+    assert!(replica
+      .execute_unprepared("SELECT count(*) FROM task")
+      .await
+      .is_ok());
+    assert!(replica
+      .execute_unprepared("INSERT INTO task (title, description) VALUES ('a', 'b')")
+      .await
+      .is_err());
+    drop(db);
+
+    config.database.max_connections = 1;
+    config.pragmas.locking_mode = Some("EXCLUSIVE".to_string());
+    let err = connect_replica(&config).await.unwrap_err();
+    assert!(matches!(
+      err,
+      Error::Startup { ref setting, .. } if setting == "database.replica.uri"
+    ));
+  }
+
+  #[tokio::test]
+  async fn unsupported_pragmas_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = file_config(&dir);
+    config.pragmas.journal_mode = Some("WAL2".to_string());
+    let err = connect(&config).await.unwrap_err();
+    assert!(matches!(
+      err,
+      Error::Startup { kind: StartupErrorKind::Config, ref setting, .. }
+        if setting == "pragmas.journal_mode"
+    ));
 
-  Ok(AppContext::new(
-    db,
-    Cache::new(cache::drivers::inmem::new()).into()
-  ))
-  
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = file_config(&dir);
+    config.pragmas.locking_mode = Some("EXCLUSIVE".to_string());
+    let err = connect(&config).await.unwrap_err();
+    assert!(matches!(
+      err,
+      Error::Startup { ref setting, .. } if setting == "pragmas.locking_mode"
+    ));
+  }
 }
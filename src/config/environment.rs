@@ -0,0 +1,96 @@
+//! # Application Environment
+//!
+//! The environment selects the configuration profile (`config/{env}.toml`),
+//! the default log format and the migration policy. It is read from the
+//! `--environment` flag or the `APP_ENV` variable by [`crate::cli`], or from
+//! `APP_ENV` alone by [`crate::app::start`], and is `development` when unset,
+//! however the binary was compiled.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Name of the variable selecting the environment.
+pub const ENV_VAR: &str = "APP_ENV";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(into = "String", from = "String")]
+pub enum Environment {
+  #[default]
+  Development,
+  Test,
+  Production,
+  /// A custom profile, e.g. `staging`. It uses the production defaults.
+  Any(String),
+}
+
+impl Environment {
+  /// Reads the environment from `APP_ENV`, `development` when unset.
+  #[must_use]
+  pub fn resolve() -> Self {
+    std::env::var(ENV_VAR).map(Self::from).unwrap_or_default()
+  }
+
+  /// Whether development conveniences such as the startup banner are on.
+  #[must_use]
+  pub fn is_debug(&self) -> bool {
+    matches!(self, Self::Development | Self::Test)
+  }
+}
+
+impl From<&str> for Environment {
+  fn from(name: &str) -> Self {
+    match name.trim().to_lowercase().as_str() {
+      "development" | "dev" => Self::Development,
+      "test" => Self::Test,
+      "production" | "prod" => Self::Production,
+      other => Self::Any(other.to_string()),
+    }
+  }
+}
+
+impl From<String> for Environment {
+  fn from(name: String) -> Self {
+    Self::from(name.as_str())
+  }
+}
+
+impl From<Environment> for String {
+  fn from(env: Environment) -> Self {
+    env.to_string()
+  }
+}
+
+impl FromStr for Environment {
+  type Err = std::convert::Infallible;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(Self::from(s))
+  }
+}
+
+impl fmt::Display for Environment {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Development => "development".fmt(f),
+      Self::Test => "test".fmt(f),
+      Self::Production => "production".fmt(f),
+      Self::Any(name) => name.fmt(f),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn can_parse_names() {
+    assert_eq!(Environment::from("Production"), Environment::Production);
+    assert_eq!(Environment::from("dev"), Environment::Development);
+    assert_eq!(
+      Environment::from("staging"),
+      Environment::Any("staging".to_string())
+    );
+    assert_eq!(Environment::Test.to_string(), "test");
+  }
+}
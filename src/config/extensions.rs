@@ -0,0 +1,172 @@
+//! # Context Extensions
+//!
+//! A type-keyed registry on [`AppContext`] that lets subsystems (a mailer, a
+//! queue, a receipt printer, ...) be plugged in at startup without adding
+//! fields to the context. Handlers read them with the [`Ext`] extractor.
+//!
+//! # Example
+//!
+//! ```rust
+//! use axum_core::response::Response;
+//! use pos_rust_local_backend::config::app_context::AppContext;
+//! use pos_rust_local_backend::config::extensions::Ext;
+//! use pos_rust_local_backend::config::format;
+//! use pos_rust_local_backend::config::routes_config::Routes;
+//! use pos_rust_local_backend::config::routing::get;
+//!
+//! pub struct Printer {
+//!     pub name: String,
+//! }
+//!
+//! async fn print(Ext(printer): Ext<Printer>) -> pos_rust_local_backend::Result<Response> {
+//!     format::text(&printer.name)
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> pos_rust_local_backend::Result<()> {
+//! let ctx = AppContext::builder()
+//!     .extension(Printer { name: "front desk".to_string() })
+//!     .build()
+//!     .await?;
+//! assert_eq!(ctx.extensions.get::<Printer>().unwrap().name, "front desk");
+//!
+//! let routes = Routes::at("/printer").add("/", get(print));
+//! # Ok(())
+//! # }
+//! ```
+use crate::config::app_context::AppContext;
+use crate::Error;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+type AnyValue = Arc<dyn Any + Send + Sync>;
+
+/// Type-keyed values shared through the [`AppContext`].
+///
+/// Cloning is cheap, the values are reference counted.
+#[derive(Clone, Default)]
+pub struct Extensions {
+  map: Arc<HashMap<TypeId, AnyValue>>,
+}
+
+impl Extensions {
+  /// Creates an empty registry.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Registers `value`, replacing the previous value of the same type.
+  ///
+  /// Returns the replaced value, if any.
+  pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<Arc<T>> {
+    Arc::make_mut(&mut self.map)
+      .insert(TypeId::of::<T>(), Arc::new(value))
+      .and_then(|previous| previous.downcast().ok())
+  }
+
+  /// Returns the value of type `T`, if registered.
+  #[must_use]
+  pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+    self
+      .map
+      .get(&TypeId::of::<T>())
+      .and_then(|value| value.clone().downcast().ok())
+  }
+
+  /// Whether a value of type `T` is registered.
+  #[must_use]
+  pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+    self.map.contains_key(&TypeId::of::<T>())
+  }
+
+  /// Number of registered values.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.map.len()
+  }
+
+  /// Whether no value is registered.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.map.is_empty()
+  }
+}
+
+impl std::fmt::Debug for Extensions {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Extensions")
+      .field("len", &self.map.len())
+      .finish()
+  }
+}
+
+/// Extracts the extension of type `T` from the [`AppContext`].
+///
+/// Rejects the request with [`Error::ExtensionMissing`] (a 500) when the
+/// extension was not registered at startup.
+#[derive(Debug)]
+pub struct Ext<T>(pub Arc<T>);
+
+impl<T> Deref for Ext<T> {
+  type Target = T;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+impl<T> Clone for Ext<T> {
+  fn clone(&self) -> Self {
+    Self(self.0.clone())
+  }
+}
+
+impl<T: Send + Sync + 'static> FromRequestParts<AppContext> for Ext<T> {
+  type Rejection = Error;
+
+  async fn from_request_parts(
+    _parts: &mut Parts,
+    ctx: &AppContext,
+  ) -> Result<Self, Self::Rejection> {
+    ctx
+      .extensions
+      .get::<T>()
+      .map(Self)
+      .ok_or(Error::ExtensionMissing(std::any::type_name::<T>()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Debug, PartialEq)]
+  struct Mailer(&'static str);
+
+  #[test]
+  fn can_insert_and_get() {
+    let mut extensions = Extensions::new();
+    assert!(extensions.get::<Mailer>().is_none());
+
+    assert!(extensions.insert(Mailer("smtp")).is_none());
+    assert_eq!(*extensions.get::<Mailer>().unwrap(), Mailer("smtp"));
+
+    let previous = extensions.insert(Mailer("sendmail")).unwrap();
+    assert_eq!(*previous, Mailer("smtp"));
+    assert_eq!(extensions.len(), 1);
+  }
+
+  #[test]
+  fn clones_do_not_see_later_inserts() {
+    let mut extensions = Extensions::new();
+    let snapshot = extensions.clone();
+    extensions.insert(42_u32);
+    assert!(extensions.contains::<u32>());
+    assert!(!snapshot.contains::<u32>());
+  }
+}
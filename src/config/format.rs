@@ -9,7 +9,7 @@
 //!
 //! ```rust
 //! use axum_core::response::Response;
-//! use serde::Serialize;//!
+//! use serde::Serialize;
 //!
 //! use pos_rust_local_backend::config::format;
 //!
@@ -0,0 +1,420 @@
+//! # Server Listener
+//!
+//! Binds the socket the server accepts connections on, as selected by
+//! [`ListenerKind`]: a TCP address, a Unix domain socket, or a socket
+//! inherited from systemd socket activation. TCP sockets can then serve
+//! HTTPS, see [`AppListener::with_tls`].
+use crate::config::tls::TlsListener;
+use crate::config::{ListenerKind, Server, Tls};
+use crate::errors::StartupErrorKind;
+use crate::{Error, Result};
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use axum::Router;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::OnceLock;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// First file descriptor passed by systemd, see `sd_listen_fds(3)`.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// The `LISTEN_PID` and `LISTEN_FDS` variables, as read by
+/// [`take_systemd_env`].
+#[cfg(unix)]
+static LISTEN_ENV: OnceLock<ListenEnv> = OnceLock::new();
+
+/// Whether the socket passed by systemd was taken, it must be owned once.
+#[cfg(unix)]
+static SYSTEMD_TAKEN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+struct ListenEnv {
+  pid: Option<String>,
+  fds: Option<String>,
+}
+
+#[cfg(unix)]
+impl ListenEnv {
+  fn read() -> Self {
+    Self {
+      pid: std::env::var("LISTEN_PID").ok(),
+      fds: std::env::var("LISTEN_FDS").ok(),
+    }
+  }
+}
+
+/// Reads the variables of systemd socket activation, then removes them from
+/// the environment so that child processes do not take the socket too.
+///
+/// Call it at the start of `main`, before the tokio runtime starts its
+/// threads: changing the environment while other threads read it is unsound.
+/// Without it, the `systemd` listener reads the variables and leaves them.
+pub fn take_systemd_env() {
+  #[cfg(unix)]
+  LISTEN_ENV.get_or_init(|| {
+    let env = ListenEnv::read();
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    env
+  });
+}
+
+/// A bound listener, ready to serve.
+#[derive(Debug)]
+pub enum AppListener {
+  Tcp(TcpListener),
+  Tls(TlsListener),
+  #[cfg(unix)]
+  Unix {
+    listener: UnixListener,
+    /// Socket file created by us, removed once the server stops.
+    path: Option<PathBuf>,
+  },
+}
+
+impl AppListener {
+  /// Binds the listener described by the server configuration.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error when the address cannot be bound, or when the
+  /// requested listener kind is not available.
+  pub async fn bind(config: &Server) -> io::Result<Self> {
+    match config.listener {
+      ListenerKind::Tcp => {
+        let listener = TcpListener::bind((config.binding.as_str(), config.port)).await?;
+        Ok(Self::Tcp(listener))
+      }
+      ListenerKind::Unix => Self::bind_unix(config),
+      ListenerKind::Systemd => Self::from_systemd(),
+    }
+  }
+
+  /// Serves HTTPS on the TCP socket, see [`crate::config::tls`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an [`Error::Startup`] when the certificates cannot be loaded,
+  /// or the listener is a Unix domain socket.
+  pub fn with_tls(self, tls: &Tls) -> Result<Self> {
+    match self {
+      Self::Tcp(listener) => Ok(Self::Tls(TlsListener::new(listener, tls)?)),
+      Self::Tls(_) => Ok(self),
+      #[cfg(unix)]
+      Self::Unix { .. } => Err(Error::startup(
+        StartupErrorKind::Config,
+        "server.tls",
+        Error::Message("TLS is only served by the `tcp` and `systemd` listeners".to_string()),
+      )),
+    }
+  }
+
+  #[cfg(unix)]
+  fn bind_unix(config: &Server) -> io::Result<Self> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let path = &config.unix_socket;
+    // A socket left behind by a previous run would make the bind fail, while
+    // one still answering belongs to a running server
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+      if metadata.file_type().is_socket() {
+        match std::os::unix::net::UnixStream::connect(path) {
+          Ok(_) => {
+            return Err(io::Error::new(
+              io::ErrorKind::AddrInUse,
+              format!("a server is already listening on {}", path.display()),
+            ))
+          }
+          Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+            std::fs::remove_file(path)?;
+          }
+          Err(_) => {}
+        }
+      }
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = config.unix_socket_mode {
+      std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode.0))?;
+    }
+    Ok(Self::Unix {
+      listener,
+      path: Some(path.clone()),
+    })
+  }
+
+  #[cfg(not(unix))]
+  fn bind_unix(_config: &Server) -> io::Result<Self> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "unix domain sockets are not supported on this platform",
+    ))
+  }
+
+  /// Takes over the first socket passed through `LISTEN_FDS`.
+  #[cfg(unix)]
+  fn from_systemd() -> io::Result<Self> {
+    use socket2::{Domain, Socket};
+    use std::os::fd::{BorrowedFd, FromRawFd};
+
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+    let env = LISTEN_ENV.get().cloned().unwrap_or_else(ListenEnv::read);
+    let pid = env
+      .pid
+      .ok_or_else(|| invalid("LISTEN_PID is not set, was the socket passed by systemd?"))?;
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+      return Err(invalid("LISTEN_PID does not match this process"));
+    }
+    let fds = env.fds.and_then(|fds| fds.parse::<u32>().ok()).unwrap_or(0);
+    if fds == 0 {
+      return Err(invalid("LISTEN_FDS does not pass any socket"));
+    }
+    if fds > 1 {
+      tracing::warn!(
+        fds,
+        "systemd passed several sockets, only the first one is used"
+      );
+    }
+    if SYSTEMD_TAKEN.swap(true, Ordering::SeqCst) {
+      return Err(invalid("the socket passed by systemd is already in use"));
+    }
+
+    // SAFETY: `LISTEN_FDS` says that systemd passed the descriptors starting
+    // at SD_LISTEN_FDS_START, open until this process closes them.
+    check_listening(unsafe { BorrowedFd::borrow_raw(SD_LISTEN_FDS_START) })?;
+    // SAFETY: systemd hands over their ownership, and `SYSTEMD_TAKEN`
+    // ensures it is taken once.
+    let socket = unsafe { Socket::from_raw_fd(SD_LISTEN_FDS_START) };
+    // Not inherited by the processes the server may start
+    socket.set_cloexec(true)?;
+    socket.set_nonblocking(true)?;
+    if socket.local_addr()?.domain() == Domain::UNIX {
+      let listener = UnixListener::from_std(socket.into())?;
+      Ok(Self::Unix {
+        listener,
+        path: None,
+      })
+    } else {
+      Ok(Self::Tcp(TcpListener::from_std(socket.into())?))
+    }
+  }
+
+  #[cfg(not(unix))]
+  fn from_systemd() -> io::Result<Self> {
+    Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      "systemd socket activation is not supported on this platform",
+    ))
+  }
+
+  /// Serves `router` until `signal` resolves, then waits for the in-flight
+  /// requests to complete.
+  ///
+  /// Requests carry the address of the client as `ConnectInfo<PeerAddr>`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error when the server fails.
+  pub async fn serve<F>(self, router: Router, signal: F) -> io::Result<()>
+  where
+    F: Future<Output = ()> + Send + 'static,
+  {
+    match self {
+      Self::Tcp(listener) => {
+        axum::serve(
+          listener,
+          router.into_make_service_with_connect_info::<PeerAddr>(),
+        )
+        .with_graceful_shutdown(signal)
+        .await
+      }
+      Self::Tls(listener) => {
+        // axum stops calling `accept`, the handshakes must stop too
+        let stop = listener.stop_token();
+        let signal = async move {
+          signal.await;
+          stop.cancel();
+        };
+        axum::serve(
+          listener,
+          router.into_make_service_with_connect_info::<PeerAddr>(),
+        )
+        .with_graceful_shutdown(signal)
+        .await
+      }
+      #[cfg(unix)]
+      Self::Unix { listener, path } => {
+        let service = router.into_make_service_with_connect_info::<PeerAddr>();
+        let result = axum::serve(listener, service)
+          .with_graceful_shutdown(signal)
+          .await;
+        if let Some(path) = path {
+          if let Err(err) = std::fs::remove_file(&path) {
+            tracing::warn!(path = %path.display(), %err, "could not remove unix socket");
+          }
+        }
+        result
+      }
+    }
+  }
+}
+
+/// Fails unless `fd` is a stream socket accepting connections, such as the
+/// one of a systemd `.socket` unit without `Accept=yes`.
+#[cfg(unix)]
+fn check_listening(fd: std::os::fd::BorrowedFd<'_>) -> io::Result<()> {
+  use socket2::{SockRef, Type};
+
+  let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+  let socket = SockRef::from(&fd);
+  let kind = socket.r#type().map_err(|err| {
+    invalid(format!(
+      "the descriptor passed by systemd is not a socket: {err}"
+    ))
+  })?;
+  if kind != Type::STREAM {
+    return Err(invalid(
+      "the socket passed by systemd is not a stream socket, use `ListenStream=`".to_string(),
+    ));
+  }
+  #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+  if !socket.is_listener()? {
+    return Err(invalid(
+      "the socket passed by systemd is not listening, was `Accept=yes` set?".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+/// The address of the client of a request, `None` over a Unix domain
+/// socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerAddr(pub Option<SocketAddr>);
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerAddr {
+  fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+    Self(Some(*stream.remote_addr()))
+  }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for PeerAddr {
+  fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+    Self(Some(*stream.remote_addr()))
+  }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, UnixListener>> for PeerAddr {
+  fn connect_info(_stream: IncomingStream<'_, UnixListener>) -> Self {
+    Self(None)
+  }
+}
+
+impl fmt::Display for AppListener {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Tcp(listener) => match listener.local_addr() {
+        Ok(addr) => write!(f, "http://{addr}"),
+        Err(_) => write!(f, "tcp"),
+      },
+      Self::Tls(listener) => match axum::serve::Listener::local_addr(listener) {
+        Ok(addr) => write!(f, "https://{addr}"),
+        Err(_) => write!(f, "tls"),
+      },
+      #[cfg(unix)]
+      Self::Unix { listener, .. } => match listener
+        .local_addr()
+        .ok()
+        .and_then(|addr| addr.as_pathname().map(|p| p.display().to_string()))
+      {
+        Some(path) => write!(f, "unix:{path}"),
+        None => write!(f, "unix"),
+      },
+    }
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn can_bind_loopback() {
+    let config = Server {
+      port: 0,
+      ..Server::default()
+    };
+    let listener = AppListener::bind(&config).await.unwrap();
+    assert!(listener.to_string().starts_with("http://127.0.0.1:"));
+  }
+
+  #[test]
+  fn only_takes_listening_stream_sockets() {
+    use std::os::fd::AsFd;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(check_listening(listener.as_fd()).is_ok());
+
+    let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let file = std::fs::File::open("Cargo.toml").unwrap();
+    for fd in [udp.as_fd(), file.as_fd()] {
+      let err = check_listening(fd).unwrap_err();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+    }
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+      use socket2::{Domain, Socket, Type};
+
+      let unbound = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+      let err = check_listening(unbound.as_fd()).unwrap_err();
+      assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{err}");
+    }
+  }
+
+  #[tokio::test]
+  async fn can_bind_and_remove_unix_socket() {
+    let path = std::env::temp_dir().join(format!("pos_backend_{}.sock", std::process::id()));
+    let config = Server {
+      listener: ListenerKind::Unix,
+      unix_socket: path.clone(),
+      ..Server::default()
+    };
+    let listener = AppListener::bind(&config).await.unwrap();
+    assert!(path.exists());
+
+    listener.serve(Router::new(), async {}).await.unwrap();
+    assert!(!path.exists());
+  }
+
+  #[tokio::test]
+  async fn keeps_the_socket_of_a_running_server() {
+    let path = std::env::temp_dir().join(format!("pos_backend_{}_live.sock", std::process::id()));
+    let config = Server {
+      listener: ListenerKind::Unix,
+      unix_socket: path.clone(),
+      ..Server::default()
+    };
+    // Left behind by a server that stopped without removing it
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    let running = AppListener::bind(&config).await.unwrap();
+
+    let err = AppListener::bind(&config).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert!(path.exists());
+
+    running.serve(Router::new(), async {}).await.unwrap();
+    assert!(!path.exists());
+  }
+}
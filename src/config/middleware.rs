@@ -0,0 +1,390 @@
+//! # HTTP Middleware
+//!
+//! The layers [`AppRoutes::into_router`](crate::config::routes_config::AppRoutes::into_router)
+//! wraps every request in, as set in the `server` section:
+//!
+//! - `cors`: the origins, methods and headers allowed to a frontend served
+//!   from another origin. `X-Request-Id` is exposed to it;
+//! - `body_limit`: bodies over the limit get `413 Payload Too Large`, whether
+//!   a handler reads them with an extractor or as a raw `Body`;
+//! - `request_timeout`: requests not answered in time get `504 Gateway
+//!   Timeout`, and `body_timeout` those whose body stalls `408 Request
+//!   Timeout`, both as an [`ErrorDetail`](crate::errors::ErrorDetail);
+//! - `compression`: responses are compressed with brotli or gzip, as
+//!   accepted by the client.
+//!
+//! ```toml
+//! [server]
+//! body_limit = 2097152
+//! request_timeout = 30000
+//! body_timeout = 10000
+//! compression = true
+//!
+//! [server.cors]
+//! allow_origins = ["http://localhost:5173"]
+//! ```
+use crate::config::request_id::X_REQUEST_ID;
+use crate::config::{Cors, Server};
+use crate::errors::StartupErrorKind;
+use crate::{Error, Result};
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, Request};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use http_body_util::BodyExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutBody;
+
+/// Wraps `router` in the layers set in `config`, see the
+/// [module documentation](self).
+///
+/// # Errors
+///
+/// Returns an [`Error::Startup`] naming the `server.cors` setting holding an
+/// invalid origin, method or header.
+pub fn apply(mut router: Router, config: &Server) -> Result<Router> {
+  // The layer limits every body, the extractors' own limit would only add a
+  // second one
+  router = router
+    .layer(DefaultBodyLimit::disable())
+    .layer(RequestBodyLimitLayer::new(config.body_limit))
+    .layer(axum::middleware::map_response(payload_too_large));
+
+  let request_timeout =
+    (config.request_timeout > 0).then(|| Duration::from_millis(config.request_timeout));
+  let body_timeout = (config.body_timeout > 0).then(|| Duration::from_millis(config.body_timeout));
+  if request_timeout.is_some() || body_timeout.is_some() {
+    router = router.layer(axum::middleware::from_fn(move |request, next| {
+      timeout(request_timeout, body_timeout, request, next)
+    }));
+  }
+
+  if config.compression {
+    router = router.layer(CompressionLayer::new());
+  }
+
+  // Outermost, so that the preflight requests and the errors get the headers
+  if let Some(cors) = &config.cors {
+    router = router.layer(cors_layer(cors)?);
+  }
+  Ok(router)
+}
+
+/// The plain text `413` of [`RequestBodyLimitLayer`] or of an extractor
+/// reading past the limit, as an [`Error::PayloadTooLarge`].
+async fn payload_too_large(response: Response) -> Response {
+  if response.status() == StatusCode::PAYLOAD_TOO_LARGE && is_plain_text(&response) {
+    return Error::PayloadTooLarge.into_response();
+  }
+  response
+}
+
+/// Answered by the rejections of axum, rather than by an [`Error`].
+fn is_plain_text(response: &Response) -> bool {
+  response
+    .headers()
+    .get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| value.starts_with("text/plain"))
+}
+
+/// Answers with an [`Error::Timeout`] once `request_timeout` elapses, and
+/// fails the reads of a body silent for `body_timeout`. The plain text `400`
+/// extractors answer a body that timed out with becomes an
+/// [`Error::BodyTimeout`].
+async fn timeout(
+  request_timeout: Option<Duration>,
+  body_timeout: Option<Duration>,
+  mut request: Request,
+  next: Next,
+) -> Response {
+  let timed_out = Arc::new(AtomicBool::new(false));
+  if let Some(body_timeout) = body_timeout {
+    let timed_out = timed_out.clone();
+    request = request.map(|body| {
+      Body::new(TimeoutBody::new(body_timeout, body).map_err(move |err| {
+        timed_out.store(true, Ordering::Relaxed);
+        err
+      }))
+    });
+  }
+  let response = match request_timeout {
+    Some(request_timeout) => tokio::time::timeout(request_timeout, next.run(request))
+      .await
+      .unwrap_or_else(|_| Error::Timeout.into_response()),
+    None => next.run(request).await,
+  };
+  if timed_out.load(Ordering::Relaxed)
+    && response.status() == StatusCode::BAD_REQUEST
+    && is_plain_text(&response)
+  {
+    return Error::BodyTimeout.into_response();
+  }
+  response
+}
+
+fn cors_layer(config: &Cors) -> Result<CorsLayer> {
+  let invalid = |setting: &str, value: &str| {
+    Error::startup(
+      StartupErrorKind::Config,
+      format!("server.cors.{setting}"),
+      Error::Message(format!("`{value}` is not valid")),
+    )
+  };
+  let is_any = |values: &[String]| values.iter().any(|value| value == "*");
+
+  let origins = if is_any(&config.allow_origins) {
+    AllowOrigin::from(Any)
+  } else {
+    let origins = config
+      .allow_origins
+      .iter()
+      .map(|origin| HeaderValue::from_str(origin).map_err(|_| invalid("allow_origins", origin)))
+      .collect::<Result<Vec<_>>>()?;
+    AllowOrigin::list(origins)
+  };
+  let methods = if is_any(&config.allow_methods) {
+    AllowMethods::from(Any)
+  } else {
+    let methods = config
+      .allow_methods
+      .iter()
+      .map(|method| {
+        Method::from_bytes(method.to_uppercase().as_bytes())
+          .map_err(|_| invalid("allow_methods", method))
+      })
+      .collect::<Result<Vec<_>>>()?;
+    AllowMethods::list(methods)
+  };
+  let headers = if is_any(&config.allow_headers) {
+    AllowHeaders::from(Any)
+  } else {
+    let headers = config
+      .allow_headers
+      .iter()
+      .map(|header| HeaderName::try_from(header).map_err(|_| invalid("allow_headers", header)))
+      .collect::<Result<Vec<_>>>()?;
+    AllowHeaders::list(headers)
+  };
+
+  Ok(
+    CorsLayer::new()
+      .allow_origin(origins)
+      .allow_methods(methods)
+      .allow_headers(headers)
+      .expose_headers([X_REQUEST_ID.clone()])
+      .max_age(Duration::from_secs(config.max_age)),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routes_config::{AppRoutes, Routes};
+  use crate::config::routing::{get, post};
+  use crate::config::testing::send;
+  use crate::config::{testing, Config};
+  use axum::body::{Bytes, HttpBody};
+  use axum::http::header::{
+    ACCEPT_ENCODING, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, CONTENT_ENCODING, ORIGIN,
+  };
+  use std::convert::Infallible;
+  use std::pin::Pin;
+  use std::task::{Context, Poll};
+
+  /// A body that never sends anything.
+  struct Stalled;
+
+  impl HttpBody for Stalled {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+      self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+      Poll::Pending
+    }
+  }
+
+  async fn echo(crate::errors::Json(body): crate::errors::Json<serde_json::Value>) -> String {
+    body.to_string()
+  }
+
+  async fn upload(body: Bytes) -> String {
+    body.len().to_string()
+  }
+
+  async fn slow() {
+    tokio::time::sleep(Duration::from_millis(500)).await;
+  }
+
+  async fn report() -> String {
+    "total ".repeat(100)
+  }
+
+  async fn app(server: Server) -> Result<Router> {
+    let routes = AppRoutes::empty().add_route(
+      Routes::at("/api")
+        .add("/echo", post(echo))
+        .add("/upload", post(upload))
+        .add("/slow", get(slow))
+        .add("/report", get(report)),
+    );
+    let config = Config {
+      server,
+      ..Config::default()
+    };
+    testing::app(config, routes).await
+  }
+
+  fn post_json(body: Body) -> Request {
+    Request::post("/api/echo")
+      .header(CONTENT_TYPE, "application/json")
+      .body(body)
+      .unwrap()
+  }
+
+  #[tokio::test]
+  async fn limits_bodies_and_time_in_json() {
+    let router = app(Server {
+      body_limit: 16,
+      request_timeout: 100,
+      body_timeout: 50,
+      ..Server::default()
+    })
+    .await
+    .unwrap();
+
+    let sent = send(&router, post_json(Body::from(r#"{"a":1}"#))).await;
+    assert_eq!(sent.status, StatusCode::OK);
+    let sent = send(&router, post_json(Body::from(r#"{"name":"a long name"}"#))).await;
+    assert_eq!(
+      sent.error(),
+      (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large")
+    );
+    let sent = send(&router, post_json(Body::new(Stalled))).await;
+    assert_eq!(
+      sent.error(),
+      (StatusCode::REQUEST_TIMEOUT, "request_timeout")
+    );
+    let sent = send(
+      &router,
+      Request::get("/api/slow").body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(sent.error(), (StatusCode::GATEWAY_TIMEOUT, "timeout"));
+  }
+
+  #[tokio::test]
+  async fn limits_raw_bodies() {
+    let router = app(Server {
+      body_limit: 16,
+      body_timeout: 50,
+      ..Server::default()
+    })
+    .await
+    .unwrap();
+    let upload = |body: Body| Request::post("/api/upload").body(body).unwrap();
+    let too_large = (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large");
+
+    let sent = send(&router, upload(Body::from("receipt"))).await;
+    assert_eq!(sent.status, StatusCode::OK);
+    // Refused from its `Content-Length`, before the handler runs
+    let sent = send(&router, upload(Body::from("a receipt too long"))).await;
+    assert_eq!(sent.error(), too_large);
+    // Without a length, once the handler has read past the limit
+    let body = tokio_util::io::ReaderStream::new(&b"a receipt too long"[..]);
+    let sent = send(&router, upload(Body::from_stream(body))).await;
+    assert_eq!(sent.error(), too_large);
+    let sent = send(&router, upload(Body::new(Stalled))).await;
+    assert_eq!(
+      sent.error(),
+      (StatusCode::REQUEST_TIMEOUT, "request_timeout")
+    );
+  }
+
+  #[tokio::test]
+  async fn compresses_responses() {
+    let request = || {
+      Request::get("/api/report")
+        .header(ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap()
+    };
+    let router = app(Server::default()).await.unwrap();
+    let sent = send(&router, request()).await;
+    assert_eq!(sent.headers[CONTENT_ENCODING], "gzip");
+
+    let router = app(Server {
+      compression: false,
+      ..Server::default()
+    })
+    .await
+    .unwrap();
+    let sent = send(&router, request()).await;
+    assert!(sent.headers.get(CONTENT_ENCODING).is_none());
+  }
+
+  #[tokio::test]
+  async fn allows_the_configured_origins() {
+    let cors = Cors {
+      allow_origins: vec!["http://localhost:5173".to_string()],
+      allow_methods: vec!["get".to_string(), "post".to_string()],
+      allow_headers: vec!["*".to_string()],
+      max_age: 60,
+    };
+    let router = app(Server {
+      cors: Some(cors.clone()),
+      ..Server::default()
+    })
+    .await
+    .unwrap();
+    let preflight = |origin: &str| {
+      Request::options("/api/echo")
+        .header(ORIGIN, origin)
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .body(Body::empty())
+        .unwrap()
+    };
+
+    let sent = send(&router, preflight("http://localhost:5173")).await;
+    assert_eq!(sent.status, StatusCode::OK);
+    assert_eq!(
+      sent.headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+      "http://localhost:5173"
+    );
+    let sent = send(&router, preflight("http://evil.example")).await;
+    assert!(sent.headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    let request = Request::get("/api/report")
+      .header(ORIGIN, "http://localhost:5173")
+      .body(Body::empty())
+      .unwrap();
+    let sent = send(&router, request).await;
+    assert_eq!(sent.headers[ACCESS_CONTROL_EXPOSE_HEADERS], "x-request-id");
+
+    let err = app(Server {
+      cors: Some(Cors {
+        allow_methods: vec!["GET POST".to_string()],
+        ..cors
+      }),
+      ..Server::default()
+    })
+    .await
+    .unwrap_err();
+    assert!(
+      err.to_string().contains("server.cors.allow_methods"),
+      "{err}"
+    );
+  }
+}
@@ -0,0 +1,245 @@
+//! # OpenAPI
+//!
+//! Builds the OpenAPI 3 document of the app from the routes of
+//! [`AppRoutes`](crate::config::routes_config::AppRoutes) and the metadata
+//! of their handlers: name, summary, tags, deprecation, and the request and
+//! response bodies declared with
+//! [`Routes::request`](crate::config::routes_config::Routes::request) and
+//! [`Routes::response`](crate::config::routes_config::Routes::response).
+//! Schemas come from the controller types deriving [`ToSchema`].
+//!
+//! The default routes serve the document at [`DOCUMENT_PATH`] and a bundled
+//! Swagger UI at [`UI_PATH`].
+use crate::config::routes_config::{BodySchema, RouteInfo};
+use crate::errors::ErrorDetail;
+use axum::http::Method;
+use std::collections::BTreeMap;
+use utoipa::openapi::content::ContentBuilder;
+use utoipa::openapi::path::{HttpMethod, OperationBuilder, ParameterBuilder, ParameterIn};
+use utoipa::openapi::request_body::RequestBodyBuilder;
+use utoipa::openapi::schema::{ArrayBuilder, Object, Type};
+use utoipa::openapi::{
+  ComponentsBuilder, Deprecated, InfoBuilder, OpenApi, OpenApiBuilder, Paths, Ref, RefOr, Required,
+  ResponseBuilder, ResponsesBuilder, Schema,
+};
+use utoipa::ToSchema;
+
+/// Where the default routes serve the document.
+pub const DOCUMENT_PATH: &str = "/api/openapi.json";
+/// Where the default routes serve Swagger UI.
+pub const UI_PATH: &str = "/api/docs";
+
+const JSON: &str = "application/json";
+
+/// Describes `routes` as an OpenAPI document.
+///
+/// Every operation documents [`ErrorDetail`] as its `default` answer, the
+/// body of every error. Handlers answering any method are left out, their
+/// methods are unknown.
+#[must_use]
+pub fn document(routes: &[RouteInfo]) -> OpenApi {
+  let mut schemas = BTreeMap::new();
+  schemas.extend(BodySchema::of::<ErrorDetail>().schemas);
+  let mut paths = Paths::new();
+
+  for route in routes {
+    let meta = &route.meta;
+    let path = openapi_path(&route.path);
+    for method in &route.methods {
+      let Some(http_method) = http_method(method) else {
+        continue;
+      };
+      // Operation ids are unique, while a handler can answer several methods
+      let operation_id = if route.methods.len() > 1 {
+        format!("{}_{}", route.name, method.as_str().to_lowercase())
+      } else {
+        route.name.clone()
+      };
+
+      let mut operation = OperationBuilder::new()
+        .operation_id(Some(operation_id))
+        .summary(meta.summary.clone())
+        .description(meta.auth.as_ref().map(|auth| format!("Requires `{auth}`.")))
+        .parameters(Some(path_parameters(&route.path)));
+      if !meta.tags.is_empty() {
+        operation = operation.tags(Some(meta.tags.clone()));
+      }
+      if meta.deprecated {
+        operation = operation.deprecated(Some(Deprecated::True));
+      }
+      // Bodies mean nothing on GET and HEAD
+      let takes_body = !matches!(http_method, HttpMethod::Get | HttpMethod::Head);
+      if let (Some(body), true) = (&meta.request, takes_body) {
+        schemas.extend(body.schemas.clone());
+        operation = operation.request_body(Some(
+          RequestBodyBuilder::new()
+            .content(
+              JSON,
+              ContentBuilder::new().schema(Some(body_ref(body))).build(),
+            )
+            .required(Some(Required::True))
+            .build(),
+        ));
+      }
+
+      let mut responses = ResponsesBuilder::new();
+      if meta.responses.is_empty() {
+        responses = responses.response("200", ResponseBuilder::new().description("OK"));
+      }
+      for (status, body) in &meta.responses {
+        let mut response =
+          ResponseBuilder::new().description(status.canonical_reason().unwrap_or_default());
+        if let Some(body) = body {
+          schemas.extend(body.schemas.clone());
+          response = response.content(
+            JSON,
+            ContentBuilder::new().schema(Some(body_ref(body))).build(),
+          );
+        }
+        responses = responses.response(status.as_str(), response);
+      }
+      let error = ContentBuilder::new()
+        .schema(Some(Ref::from_schema_name(ErrorDetail::name())))
+        .build();
+      responses = responses.response(
+        "default",
+        ResponseBuilder::new()
+          .description("Error")
+          .content(JSON, error),
+      );
+
+      paths.add_path_operation(
+        &path,
+        vec![http_method],
+        operation.responses(responses).build(),
+      );
+    }
+  }
+
+  OpenApiBuilder::new()
+    .info(
+      InfoBuilder::new()
+        .title(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION")),
+    )
+    .paths(paths)
+    .components(Some(
+      ComponentsBuilder::new().schemas_from_iter(schemas).build(),
+    ))
+    .build()
+}
+
+fn http_method(method: &Method) -> Option<HttpMethod> {
+  Some(match *method {
+    Method::GET => HttpMethod::Get,
+    Method::POST => HttpMethod::Post,
+    Method::PUT => HttpMethod::Put,
+    Method::PATCH => HttpMethod::Patch,
+    Method::DELETE => HttpMethod::Delete,
+    Method::HEAD => HttpMethod::Head,
+    Method::OPTIONS => HttpMethod::Options,
+    Method::TRACE => HttpMethod::Trace,
+    _ => return None,
+  })
+}
+
+fn body_ref(body: &BodySchema) -> RefOr<Schema> {
+  let schema = Ref::from_schema_name(&body.name);
+  if body.list {
+    ArrayBuilder::new().items(schema).into()
+  } else {
+    schema.into()
+  }
+}
+
+/// The path with wildcards written as plain parameters, `{*rest}` as
+/// `{rest}`.
+fn openapi_path(path: &str) -> String {
+  path.replace("{*", "{")
+}
+
+/// The parameters of the path, all strings as the routes do not tell their
+/// type.
+fn path_parameters(path: &str) -> Vec<utoipa::openapi::path::Parameter> {
+  path
+    .split('/')
+    .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+    .map(|name| {
+      ParameterBuilder::new()
+        .name(name.trim_start_matches('*'))
+        .parameter_in(ParameterIn::Path)
+        .required(Required::True)
+        .schema(Some(Object::with_type(Type::String)))
+        .build()
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routes_config::{AppRoutes, Routes};
+  use crate::config::routing::get;
+  use crate::config::testing::handler;
+  use axum::http::StatusCode;
+  use serde::{Deserialize, Serialize};
+
+  #[derive(Deserialize, ToSchema)]
+  #[allow(dead_code)]
+  struct NewItem {
+    name: String,
+  }
+
+  #[derive(Serialize, ToSchema)]
+  struct Item {
+    id: i32,
+    name: String,
+  }
+
+  #[test]
+  fn documents_routes_with_their_schemas() {
+    let routes = AppRoutes::empty().add_route(
+      Routes::at("/items")
+        .add("/", get(handler).post(handler))
+        .request::<NewItem>()
+        .response::<Item>(StatusCode::CREATED)
+        .add("/{id}", get(handler))
+        .name("get_item")
+        .summary("Get an item")
+        .tag("items")
+        .requires_role("manager")
+        .deprecated()
+        .response_list::<Item>(StatusCode::OK),
+    );
+    let document = serde_json::to_value(routes.openapi()).unwrap();
+
+    let item = &document["paths"]["/items/{id}"]["get"];
+    assert_eq!(item["operationId"], "get_item");
+    assert_eq!(item["summary"], "Get an item");
+    assert_eq!(item["description"], "Requires `role:manager`.");
+    assert_eq!(item["tags"][0], "items");
+    assert_eq!(item["deprecated"], true);
+    assert_eq!(item["parameters"][0]["name"], "id");
+    assert_eq!(
+      item["responses"]["200"]["content"][JSON]["schema"]["items"]["$ref"],
+      "#/components/schemas/Item"
+    );
+    assert_eq!(
+      item["responses"]["default"]["content"][JSON]["schema"]["$ref"],
+      "#/components/schemas/ErrorDetail"
+    );
+
+    let create = &document["paths"]["/items"]["post"];
+    assert_eq!(create["operationId"], "items_post");
+    assert_eq!(
+      create["requestBody"]["content"][JSON]["schema"]["$ref"],
+      "#/components/schemas/NewItem"
+    );
+    for schema in ["NewItem", "Item", "ErrorDetail"] {
+      assert!(
+        document["components"]["schemas"][schema].is_object(),
+        "{schema}"
+      );
+    }
+  }
+}
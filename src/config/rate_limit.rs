@@ -0,0 +1,494 @@
+//! # Rate Limiting
+//!
+//! Limits how often a client calls the API, to keep scripts on a shared
+//! network away from the login and payment routes. Limits are token buckets:
+//! a client may send `requests` at once, then one more every `window /
+//! requests`. Over the limit, requests get `429 Too Many Requests` with a
+//! `Retry-After` header, see [`Error::TooManyRequests`].
+//!
+//! - `server.rate_limit` counts every request of a client, whatever the
+//!   route, but those to the health probes and the other routes marked with
+//!   [`Routes::rate_limit_exempt`](crate::config::routes_config::Routes::rate_limit_exempt);
+//! - [`Routes::rate_limit`](crate::config::routes_config::Routes::rate_limit)
+//!   counts the requests to one handler on their own.
+//!
+//! Requests are counted by client IP, by `X-Api-Key` or for everyone, see
+//! [`RateLimitKey`]. Only the keys listed, hashed, in `server.api_keys` are
+//! counted on their own: any other key would give a client a fresh bucket.
+//! Keys are hashed in the cache too.
+//!
+//! Requests over a Unix domain socket have no address: they count as those
+//! of one client. With `server.trusted_proxy`, the socket is taken to be the
+//! one of a reverse proxy, and the address it sends in `X-Forwarded-For` or
+//! `Forwarded` is used. Only set it when nothing else can connect to the
+//! socket, as any client could send another address with each request.
+//!
+//! The buckets are kept in [`AppContext::cache`], with the `null` driver
+//! nothing is limited. Updating a bucket is only atomic within a process: with
+//! a cache shared by several servers, each may let a few extra requests
+//! through.
+//!
+//! ```toml
+//! [server.rate_limit]
+//! requests = 300
+//! window = 60000
+//! key = "ip"
+//! ```
+//!
+//! [`AppContext::cache`]: crate::config::app_context::AppContext::cache
+use crate::cache::Cache;
+use crate::config::listener::PeerAddr;
+use crate::config::routes_config::RouteMeta;
+use crate::config::{RateLimit, RateLimitKey, Server};
+use crate::Error;
+use axum::extract::{ConnectInfo, MatchedPath, Request};
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// Header identifying the client for [`RateLimitKey::ApiKey`].
+pub static X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+static FORWARDED: HeaderName = HeaderName::from_static("forwarded");
+
+/// Longer `X-Api-Key` values are not hashed, they cannot be a known key.
+const MAX_API_KEY_LEN: usize = 256;
+
+/// Locks the buckets are spread over.
+const LOCKS: usize = 64;
+
+/// Counts the requests in the cache.
+pub(crate) struct RateLimiter {
+  cache: Arc<Cache>,
+  /// The limit of `server.rate_limit`.
+  app: Option<RateLimit>,
+  /// `server.api_keys`, lowercase.
+  api_keys: HashSet<String>,
+  /// `server.trusted_proxy`.
+  trusted_proxy: bool,
+  /// Reading then writing a bucket is not atomic in the cache, so each bucket
+  /// is updated under the lock its key hashes to.
+  locks: Box<[Mutex<()>]>,
+}
+
+impl RateLimiter {
+  /// Applies the limits and settings of `server`.
+  pub(crate) fn new(cache: Arc<Cache>, server: &Server) -> Self {
+    Self {
+      cache,
+      app: server.rate_limit.clone(),
+      api_keys: server
+        .api_keys
+        .iter()
+        .map(|key| key.to_lowercase())
+        .collect(),
+      trusted_proxy: server.trusted_proxy,
+      locks: (0..LOCKS).map(|_| Mutex::new(())).collect(),
+    }
+  }
+
+  fn lock(&self, key: &str) -> &Mutex<()> {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    &self.locks[hasher.finish() as usize % self.locks.len()]
+  }
+
+  /// Takes a token from the bucket at `key`, or tells how long until one is
+  /// available.
+  async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<(), Duration> {
+    if limit.window == 0 {
+      return Ok(());
+    }
+    if limit.requests == 0 {
+      return Err(Duration::from_millis(limit.window));
+    }
+    let _lock = self.lock(key).lock().await;
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis();
+    let capacity = f64::from(limit.requests);
+    // Tokens per millisecond
+    let rate = capacity / limit.window as f64;
+
+    let (tokens, last) = match self.cache.get(key).await {
+      Ok(bucket) => bucket
+        .as_deref()
+        .and_then(parse_bucket)
+        .unwrap_or((capacity, now)),
+      Err(err) => {
+        tracing::warn!(%err, key, "rate limit not enforced, the cache failed");
+        return Ok(());
+      }
+    };
+    let tokens = (tokens + now.saturating_sub(last) as f64 * rate).min(capacity);
+    if tokens < 1.0 {
+      let wait = ((1.0 - tokens) / rate).ceil();
+      return Err(Duration::from_millis(wait as u64));
+    }
+
+    // Dropped once the bucket would be full again
+    let bucket = format!("{}:{now}", tokens - 1.0);
+    let expiry = Duration::from_millis(limit.window);
+    if let Err(err) = self.cache.insert_with_expiry(key, &bucket, expiry).await {
+      tracing::warn!(%err, key, "rate limit not enforced, the cache failed");
+    }
+    Ok(())
+  }
+
+  /// Who the request is counted for.
+  fn client(&self, request: &Request, key: RateLimitKey) -> String {
+    match key {
+      RateLimitKey::Ip => format!("ip:{}", self.client_ip(request)),
+      RateLimitKey::ApiKey => request
+        .headers()
+        .get(&X_API_KEY)
+        .filter(|key| key.len() <= MAX_API_KEY_LEN)
+        .map(|key| sha256_hex(key.as_bytes()))
+        .filter(|hash| self.api_keys.contains(hash))
+        .map_or_else(
+          || format!("ip:{}", self.client_ip(request)),
+          |hash| format!("key:{hash}"),
+        ),
+      RateLimitKey::Route => "all".to_string(),
+    }
+  }
+
+  /// The address of the peer, or over a Unix domain socket the one forwarded
+  /// by a trusted proxy.
+  fn client_ip(&self, request: &Request) -> String {
+    let forwarded = match request.extensions().get::<ConnectInfo<PeerAddr>>() {
+      Some(ConnectInfo(PeerAddr(Some(addr)))) => return addr.ip().to_string(),
+      Some(ConnectInfo(PeerAddr(None))) if self.trusted_proxy => forwarded_for(request.headers()),
+      _ => None,
+    };
+    forwarded.map_or_else(|| "local".to_string(), |ip| ip.to_string())
+  }
+}
+
+/// `tokens:last`, the tokens left and when, in milliseconds.
+fn parse_bucket(bucket: &str) -> Option<(f64, u128)> {
+  let (tokens, last) = bucket.split_once(':')?;
+  Some((tokens.parse().ok()?, last.parse().ok()?))
+}
+
+/// The client as seen by the last proxy: the last address of
+/// `X-Forwarded-For`, or of `Forwarded`.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+  let last = |name: &HeaderName| {
+    headers
+      .get_all(name)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .last()
+      .map(str::trim)
+  };
+  if let Some(addr) = last(&X_FORWARDED_FOR) {
+    return addr.parse().ok();
+  }
+  // `for=192.0.2.1`, `for="192.0.2.1:4000"` or `for="[2001:db8::1]:4000"`
+  let node = last(&FORWARDED)?
+    .split(';')
+    .find_map(|pair| pair.trim().strip_prefix("for="))?
+    .trim_matches('"');
+  match node.strip_prefix('[') {
+    Some(ipv6) => ipv6.split(']').next()?.parse().ok(),
+    None => node.split(':').next()?.parse().ok(),
+  }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+  Sha256::digest(data)
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+/// Middleware applying `server.rate_limit`, then the limit of the route, see
+/// the [module documentation](self).
+pub(crate) async fn rate_limit(
+  limiter: Arc<RateLimiter>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let exempt = request
+    .extensions()
+    .get::<RouteMeta>()
+    .is_some_and(|meta| meta.rate_limit_exempt);
+  if let Some(limit) = limiter.app.as_ref().filter(|_| !exempt) {
+    let key = format!("rate_limit:app:{}", limiter.client(&request, limit.key));
+    if let Err(wait) = limiter.acquire(&key, limit).await {
+      return Error::TooManyRequests(wait).into_response();
+    }
+  }
+
+  let route = request
+    .extensions()
+    .get::<RouteMeta>()
+    .and_then(|meta| meta.rate_limit.as_ref())
+    .zip(request.extensions().get::<MatchedPath>());
+  if let Some((limit, path)) = route {
+    let key = format!(
+      "rate_limit:{} {}:{}",
+      request.method(),
+      path.as_str(),
+      limiter.client(&request, limit.key)
+    );
+    if let Err(wait) = limiter.acquire(&key, limit).await {
+      return Error::TooManyRequests(wait).into_response();
+    }
+  }
+  next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routes_config::{AppRoutes, Routes};
+  use crate::config::routing::{get, post};
+  use crate::config::testing::{self, handler};
+  use crate::config::{CacheConfig, Config, Server};
+  use axum::body::Body;
+  use axum::http::header::RETRY_AFTER;
+  use axum::http::StatusCode;
+  use axum::Router;
+  use std::net::SocketAddr;
+
+  async fn app(config: Config) -> Router {
+    let routes = AppRoutes::empty()
+      .add_route(
+        Routes::at("/api")
+          .add("/login", post(handler))
+          .rate_limit(RateLimit::per_minute(2))
+          .add("/payments", post(handler))
+          .rate_limit(RateLimit::per_minute(1).by(RateLimitKey::ApiKey))
+          .add("/tasks", get(handler)),
+      )
+      .add_route(crate::controllers::health::routes());
+    testing::app(config, routes).await.unwrap()
+  }
+
+  /// Sends `request` from `ip`, returns the status and `Retry-After`.
+  async fn send_from(router: &Router, request: Request, ip: &str) -> (StatusCode, Option<String>) {
+    let mut request = request;
+    let addr: SocketAddr = format!("{ip}:4000").parse().unwrap();
+    request
+      .extensions_mut()
+      .insert(ConnectInfo(PeerAddr(Some(addr))));
+    let sent = testing::send(router, request).await;
+    if sent.status == StatusCode::TOO_MANY_REQUESTS {
+      assert_eq!(sent.body["error"], "too_many_requests");
+    }
+    let retry_after = sent
+      .headers
+      .get(RETRY_AFTER)
+      .map(|value| value.to_str().unwrap().to_string());
+    (sent.status, retry_after)
+  }
+
+  fn post_to(uri: &str) -> Request {
+    Request::post(uri).body(Body::empty()).unwrap()
+  }
+
+  #[tokio::test]
+  async fn limits_routes_per_client() {
+    let router = app(Config::default()).await;
+    let ok = (StatusCode::OK, None);
+
+    assert_eq!(
+      send_from(&router, post_to("/api/login"), "10.0.0.2").await,
+      ok
+    );
+    assert_eq!(
+      send_from(&router, post_to("/api/login"), "10.0.0.2").await,
+      ok
+    );
+    let (status, retry_after) = send_from(&router, post_to("/api/login"), "10.0.0.2").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = retry_after.unwrap().parse().unwrap();
+    assert!((1..=30).contains(&retry_after), "{retry_after}");
+
+    // Another till, another route, a route without limit
+    assert_eq!(
+      send_from(&router, post_to("/api/login"), "10.0.0.3").await,
+      ok
+    );
+    assert_eq!(
+      send_from(&router, post_to("/api/payments"), "10.0.0.2").await,
+      ok
+    );
+    for _ in 0..3 {
+      let request = Request::get("/api/tasks").body(Body::empty()).unwrap();
+      assert_eq!(send_from(&router, request, "10.0.0.2").await, ok);
+    }
+  }
+
+  #[tokio::test]
+  async fn limits_known_api_keys_apart() {
+    let config = Config {
+      server: Server {
+        api_keys: ["till-1", "till-2"]
+          .map(|key| sha256_hex(key.as_bytes()).to_uppercase())
+          .to_vec(),
+        ..Server::default()
+      },
+      ..Config::default()
+    };
+    let router = app(config).await;
+    let payment = |key: &str| {
+      Request::post("/api/payments")
+        .header(&X_API_KEY, key)
+        .body(Body::empty())
+        .unwrap()
+    };
+
+    assert_eq!(
+      send_from(&router, payment("till-1"), "10.0.0.2").await.0,
+      StatusCode::OK
+    );
+    assert_eq!(
+      send_from(&router, payment("till-1"), "10.0.0.3").await.0,
+      StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+      send_from(&router, payment("till-2"), "10.0.0.2").await.0,
+      StatusCode::OK
+    );
+    // Unknown keys count as their address
+    assert_eq!(
+      send_from(&router, payment("forged-1"), "10.0.0.4").await.0,
+      StatusCode::OK
+    );
+    assert_eq!(
+      send_from(&router, payment("forged-2"), "10.0.0.4").await.0,
+      StatusCode::TOO_MANY_REQUESTS
+    );
+  }
+
+  /// A request over the Unix domain socket.
+  fn unix_request(uri: &str, headers: &[(&str, &str)]) -> Request {
+    let mut request = Request::post(uri).body(Body::empty()).unwrap();
+    for (name, value) in headers {
+      request.headers_mut().append(
+        HeaderName::from_bytes(name.as_bytes()).unwrap(),
+        value.parse().unwrap(),
+      );
+    }
+    request.extensions_mut().insert(ConnectInfo(PeerAddr(None)));
+    request
+  }
+
+  #[test]
+  fn reads_the_client_forwarded_by_a_trusted_proxy() {
+    let limiter = |trusted_proxy| {
+      let server = Server {
+        trusted_proxy,
+        ..Server::default()
+      };
+      RateLimiter::new(
+        Arc::new(Cache::new(crate::cache::drivers::inmem::new())),
+        &server,
+      )
+    };
+    let (trusted, untrusted) = (limiter(true), limiter(false));
+
+    let forwarded = [
+      (vec![], "local"),
+      (
+        vec![("x-forwarded-for", "203.0.113.9, 10.0.0.2")],
+        "10.0.0.2",
+      ),
+      (
+        vec![(
+          "forwarded",
+          r#"for=203.0.113.9, for="[2001:db8::1]:4000";proto=https"#,
+        )],
+        "2001:db8::1",
+      ),
+      (vec![("forwarded", "for=10.0.0.5:4000")], "10.0.0.5"),
+      (vec![("x-forwarded-for", "not-an-ip")], "local"),
+    ];
+    for (headers, ip) in forwarded {
+      let request = unix_request("/", &headers);
+      assert_eq!(trusted.client_ip(&request), ip, "{headers:?}");
+      assert_eq!(untrusted.client_ip(&request), "local", "{headers:?}");
+    }
+
+    // Only a proxy on the Unix domain socket is believed
+    let mut request = unix_request("/", &[("x-forwarded-for", "10.0.0.2")]);
+    let addr: SocketAddr = "192.0.2.7:4000".parse().unwrap();
+    request
+      .extensions_mut()
+      .insert(ConnectInfo(PeerAddr(Some(addr))));
+    assert_eq!(trusted.client_ip(&request), "192.0.2.7");
+  }
+
+  #[tokio::test]
+  async fn forged_addresses_share_the_bucket_of_the_socket() {
+    let router = app(Config::default()).await;
+    let login = |ip: &str| unix_request("/api/login", &[("x-forwarded-for", ip)]);
+
+    for ip in ["10.0.0.2", "10.0.0.3"] {
+      assert_eq!(
+        testing::send(&router, login(ip)).await.status,
+        StatusCode::OK
+      );
+    }
+    let sent = testing::send(&router, login("10.0.0.4")).await;
+    assert_eq!(sent.status, StatusCode::TOO_MANY_REQUESTS);
+  }
+
+  #[tokio::test]
+  async fn limits_the_whole_app_from_config() {
+    let config = |cache| Config {
+      server: Server {
+        rate_limit: Some(RateLimit::per_second(3)),
+        ..Server::default()
+      },
+      cache,
+      ..Config::default()
+    };
+    let tasks = || Request::get("/api/tasks").body(Body::empty()).unwrap();
+
+    let router = app(config(CacheConfig::default())).await;
+    for _ in 0..3 {
+      assert_eq!(
+        send_from(&router, tasks(), "10.0.0.2").await.0,
+        StatusCode::OK
+      );
+    }
+    assert_eq!(
+      send_from(&router, tasks(), "10.0.0.2").await,
+      (StatusCode::TOO_MANY_REQUESTS, Some("1".to_string()))
+    );
+    assert_eq!(
+      send_from(&router, tasks(), "10.0.0.3").await.0,
+      StatusCode::OK
+    );
+    // The probes of a supervisor are never refused
+    let health = Request::get("/_health").body(Body::empty()).unwrap();
+    assert_eq!(
+      send_from(&router, health, "10.0.0.2").await.0,
+      StatusCode::OK
+    );
+
+    // Without a cache to keep the buckets, nothing is limited
+    let router = app(config(CacheConfig::Null)).await;
+    for _ in 0..5 {
+      assert_eq!(
+        send_from(&router, tasks(), "10.0.0.2").await.0,
+        StatusCode::OK
+      );
+    }
+  }
+}
@@ -0,0 +1,178 @@
+//! # Request ID
+//!
+//! Tags every request with an ID, to match what a till saw with the server
+//! logs:
+//!
+//! - the `X-Request-Id` header of the request is kept when it is a sensible
+//!   ID, a UUID is generated otherwise;
+//! - the request runs in a `request` span carrying the ID, method, path and
+//!   status, so the `controller_error` lines of [`crate::errors`] name it;
+//! - the response carries the ID in `X-Request-Id`, and every
+//!   [`ErrorDetail`](crate::errors::ErrorDetail) built for the request in its
+//!   `request_id` field.
+//!
+//! Handlers read it with `Extension<RequestId>`.
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::fmt;
+use tracing::Instrument;
+
+/// Header carrying the ID, in both directions.
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest ID accepted from a client.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+  static CURRENT: RequestId;
+}
+
+/// The ID of a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+  /// Keeps `id` when it is short and made of letters, digits, `-`, `_`, `.`
+  /// or `:`, the characters of the usual ID formats.
+  #[must_use]
+  pub fn parse(id: &str) -> Option<Self> {
+    let valid = !id.is_empty()
+      && id.len() <= MAX_LENGTH
+      && id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    valid.then(|| Self(id.to_string()))
+  }
+
+  /// A new random ID.
+  #[must_use]
+  pub fn generate() -> Self {
+    Self(uuid::Uuid::new_v4().to_string())
+  }
+
+  /// The ID of the request being handled, if any.
+  #[must_use]
+  pub fn current() -> Option<Self> {
+    CURRENT.try_with(Clone::clone).ok()
+  }
+
+  #[must_use]
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Display for RequestId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+/// Middleware tagging the request with its [`RequestId`], see the
+/// [module documentation](self).
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+  let id = request
+    .headers()
+    .get(&X_REQUEST_ID)
+    .and_then(|id| id.to_str().ok())
+    .and_then(RequestId::parse)
+    .unwrap_or_else(RequestId::generate);
+  request.extensions_mut().insert(id.clone());
+
+  let span = tracing::info_span!(
+    "request",
+    request_id = %id,
+    method = %request.method(),
+    path = %request.uri().path(),
+    status = tracing::field::Empty,
+  );
+  let mut response = CURRENT
+    .scope(id.clone(), next.run(request))
+    .instrument(span.clone())
+    .await;
+  span.record("status", response.status().as_u16());
+  tracing::debug!(parent: &span, "request finished");
+
+  // Only holds the characters checked by `parse`, or a UUID
+  if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+    response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+  }
+  response
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routes_config::{AppRoutes, Routes};
+  use crate::config::routing::get;
+  use crate::config::testing::{app, offline_context, send};
+  use crate::config::Config;
+  use crate::Error;
+  use axum::body::Body;
+  use axum::http::StatusCode;
+
+  async fn missing() -> Error {
+    Error::NotFound
+  }
+
+  fn request(uri: &str, id: Option<&str>) -> Request {
+    let mut request = Request::get(uri);
+    if let Some(id) = id {
+      request = request.header(&X_REQUEST_ID, id);
+    }
+    request.body(Body::empty()).unwrap()
+  }
+
+  #[tokio::test]
+  async fn tags_responses_and_errors() {
+    let routes = AppRoutes::empty().add_route(Routes::at("/api/tasks").add("/{id}", get(missing)));
+    let router = app(Config::default(), routes).await.unwrap();
+    let tagged = |id| {
+      let router = router.clone();
+      async move {
+        let sent = send(&router, request("/api/tasks/1", id)).await;
+        assert_eq!(sent.status, StatusCode::NOT_FOUND);
+        let header = sent.headers[&X_REQUEST_ID].to_str().unwrap().to_string();
+        assert_eq!(sent.body["request_id"], header.as_str());
+        header
+      }
+    };
+
+    assert_eq!(tagged(Some("till-3:0042")).await, "till-3:0042");
+    let generated = tagged(Some("not an id")).await;
+    assert!(uuid::Uuid::parse_str(&generated).is_ok(), "{generated}");
+    assert_ne!(tagged(None).await, generated);
+  }
+
+  #[tokio::test]
+  async fn tags_the_requests_aborted_by_the_shutdown() {
+    let ctx = offline_context(Config::default()).await;
+    let router = AppRoutes::empty()
+      .add_route(Routes::at("/api").add("/slow", get(std::future::pending::<()>)))
+      .into_router(&ctx)
+      .unwrap();
+    ctx.shutdown.abort_in_flight();
+
+    let sent = send(&router, request("/api/slow", Some("till-3:0042"))).await;
+    assert_eq!(
+      sent.error(),
+      (StatusCode::SERVICE_UNAVAILABLE, "shutting_down")
+    );
+    assert_eq!(sent.headers[&X_REQUEST_ID], "till-3:0042");
+    assert_eq!(sent.body["request_id"], "till-3:0042");
+  }
+
+  #[test]
+  fn keeps_sensible_ids_only() {
+    assert_eq!(
+      RequestId::parse("till-3:0042").map(|id| id.to_string()),
+      Some("till-3:0042".to_string())
+    );
+    assert_eq!(RequestId::parse(""), None);
+    assert_eq!(RequestId::parse("till 3"), None);
+    assert_eq!(RequestId::parse(&"a".repeat(MAX_LENGTH + 1)), None);
+    assert_ne!(RequestId::generate(), RequestId::generate());
+  }
+}
@@ -1,18 +1,58 @@
+use crate::config::api_version::{select_version, ApiVersion, VersionSelector};
 use crate::config::app_context::AppContext;
-use axum::Router;
+use crate::config::rate_limit::{rate_limit, RateLimiter};
+use crate::config::request_id::request_id;
+use crate::config::routing::Endpoint;
+use crate::config::static_assets::StaticAssets;
+use crate::config::{middleware, openapi, RateLimit};
+use crate::{shutdown, Error, Result};
+use axum::extract::{MatchedPath, Request};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{MethodRouter, Route};
+use axum::{Extension, Router};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tower::{Layer, Service};
+use utoipa::openapi::{OpenApi, RefOr, Schema};
+use utoipa::ToSchema;
 
 #[derive(Clone)]
 pub struct AppRoutes {
   prefix: Option<String>,
   routes: Vec<Routes>,
+  versions: Vec<ApiVersion>,
+  default_version: Option<String>,
+  layers: Vec<RouteLayer>,
+  /// Serve the API documentation, see [`crate::controllers::docs`].
+  docs: bool,
+  /// Answers the requests no route matches.
+  assets: Option<StaticAssets>,
 }
 
 impl AppRoutes {
-  /// Creates a new [`AppRoutes`] instance with default settings.
+  /// Creates a new [`AppRoutes`] instance with the built-in routes:
+  /// `/_health` and `/_ready`, see [`crate::controllers::health`], and the
+  /// API documentation, see [`crate::controllers::docs`], unless
+  /// `server.docs` turns it off.
   pub fn with_default_routes() -> Self {
+    let mut routes = Self::empty().add_route(crate::controllers::health::routes());
+    routes.docs = true;
+    routes
+  }
+
+  /// Creates a new [`AppRoutes`] instance without any route.
+  pub fn empty() -> Self {
     Self {
       prefix: None,
       routes: Vec::new(),
+      versions: Vec::new(),
+      default_version: None,
+      layers: Vec::new(),
+      docs: false,
+      assets: None,
     }
   }
 
@@ -22,30 +62,274 @@ impl AppRoutes {
     self
   }
 
+  /// Serves an API version under `/api/{name}`, see
+  /// [`crate::config::api_version`].
+  pub fn add_version(mut self, version: ApiVersion) -> Self {
+    self.versions.push(version);
+    self
+  }
+
+  /// Sets the version serving unversioned `/api/...` requests without an
+  /// `Accept-Version` header, the last added version by default.
+  ///
+  /// # Panics
+  ///
+  /// When no version named `name` was added.
+  #[must_use]
+  #[track_caller]
+  pub fn default_version(mut self, name: &str) -> Self {
+    assert!(
+      self.versions.iter().any(|version| version.name() == name),
+      "add the API version `{name}` before making it the default"
+    );
+    self.default_version = Some(name.to_string());
+    self
+  }
+
+  /// The prefix shared by every route, if any.
+  pub fn get_prefix(&self) -> Option<&String> {
+    self.prefix.as_ref()
+  }
+
+  /// The registered routes.
+  pub fn get_routes(&self) -> &[Routes] {
+    self.routes.as_ref()
+  }
+
+  /// The registered API versions.
+  pub fn get_versions(&self) -> &[ApiVersion] {
+    self.versions.as_ref()
+  }
+
+  /// Leaves out the API documentation of [`Self::with_default_routes`].
+  #[must_use]
+  pub fn without_docs(mut self) -> Self {
+    self.docs = false;
+    self
+  }
+
+  /// The routes outside the versions, the API documentation included.
+  fn unversioned_routes(&self) -> Vec<Routes> {
+    let docs = self.docs.then(crate::controllers::docs::routes);
+    self.routes.iter().cloned().chain(docs).collect()
+  }
+
+  /// The routes, then the routes of every version as mounted.
+  fn all_routes(&self) -> Vec<Routes> {
+    let versioned = self.versions.iter().flat_map(ApiVersion::mounted_routes);
+    self
+      .unversioned_routes()
+      .into_iter()
+      .chain(versioned)
+      .collect()
+  }
+
   /// Sets a prefix for all config.
   pub fn prefix(mut self, prefix: &str) -> Self {
     self.prefix = Some(prefix.to_string());
     self
   }
 
+  /// Wraps every route, and the fallback answering unknown paths, in
+  /// `layer`.
+  ///
+  /// As with [`Router::layer`], the last layer added runs first.
+  #[must_use]
+  pub fn layer<L>(mut self, layer: L) -> Self
+  where
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+  {
+    self.layers.push(RouteLayer::new(layer));
+    self
+  }
+
+  /// Serves the frontend for the requests no route matches, see
+  /// [`crate::config::static_assets`].
+  ///
+  /// Its excluded paths are relative to the prefix of the app.
+  #[must_use]
+  pub fn assets(mut self, assets: StaticAssets) -> Self {
+    self.assets = Some(assets);
+    self
+  }
+
+  /// Describes every registered handler with its full path, in registration
+  /// order.
+  pub fn collect(&self) -> Vec<RouteInfo> {
+    let prefix = self.prefix.as_deref().unwrap_or("");
+    self
+      .all_routes()
+      .into_iter()
+      .flat_map(|route| {
+        let route_prefix = route.prefix.unwrap_or_default();
+        route.handlers.into_iter().map(move |handler| {
+          let path = join_path(&[prefix, &route_prefix, &handler.uri]);
+          RouteInfo {
+            name: handler
+              .meta
+              .name
+              .clone()
+              .unwrap_or_else(|| default_name(&path)),
+            path,
+            methods: handler.actions,
+            meta: handler.meta,
+          }
+        })
+      })
+      .collect()
+  }
+
+  /// The OpenAPI document of every route, see [`crate::config::openapi`].
+  pub fn openapi(&self) -> OpenApi {
+    openapi::document(&self.collect())
+  }
+
   /// Converts the `AppRoutes` into an Axum `Router`.
-  pub fn into_router(self, ctx: &AppContext) -> Router {
+  ///
+  /// Every matched request carries the [`RouteMeta`] of its handler as an
+  /// extension, for the middleware and handlers to read, and every request
+  /// its [`RequestId`](crate::config::request_id::RequestId). Requests are
+  /// counted in flight until their response is sent, see [`crate::shutdown`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an [`Error::RouteConflict`] when two handlers answer the same
+  /// requests, rather than letting axum panic, or an [`Error::Startup`] when
+  /// the `server` settings of [`crate::config::middleware`] are invalid.
+  pub fn into_router(mut self, ctx: &AppContext) -> Result<Router> {
+    self.docs &= ctx.config.server.docs;
+    let routes = self.collect();
+    let prefix = self.prefix.as_deref().unwrap_or("");
+    let mut checked = routes.clone();
+    if self.docs {
+      // Swagger UI is an axum router of its own, missing from the routes
+      let ui = crate::controllers::docs::swagger_ui_paths(prefix);
+      checked.extend(ui.into_iter().map(|path| RouteInfo {
+        path,
+        methods: vec![Method::GET],
+        name: "swagger_ui".to_string(),
+        meta: RouteMeta::default(),
+      }));
+    }
+    check_conflicts(&checked)?;
+    let metas = Arc::new(RouteMetas::new(&routes));
+
+    let assets = self
+      .assets
+      .as_ref()
+      .map(|assets| assets.clone().excluding_under(prefix));
+    let selector = VersionSelector::new(prefix, &self.versions, self.default_version.as_deref());
+    let limiter = (ctx.config.server.rate_limit.is_some()
+      || routes.iter().any(|route| route.meta.rate_limit.is_some()))
+    .then(|| Arc::new(RateLimiter::new(ctx.cache.clone(), &ctx.config.server)));
+
     let mut router = Router::new();
+    if self.docs {
+      router = crate::controllers::docs::swagger_ui(prefix);
+    }
+    // The assets answer what the innermost router does not
+    let fallback = if selector.is_some() {
+      None
+    } else {
+      assets.clone()
+    };
+    let router = self
+      .build(
+        router,
+        self.unversioned_routes(),
+        fallback,
+        &metas,
+        limiter.as_ref(),
+      )
+      .layer(Extension(Arc::new(openapi::document(&routes))))
+      .with_state(ctx.clone());
 
-    for route in self.routes {
-      let prefix = self.prefix.as_deref().unwrap_or("");
-      let route_prefix = route.prefix.as_deref().unwrap_or("");
-      let full_prefix = format!("{}{}", prefix, route_prefix);
+    let router = match selector {
+      // Requests no other route answers go to the versions. The version is
+      // picked before routing, so the middleware wraps their whole router.
+      Some(selector) => {
+        let versions = self.versions.iter().flat_map(ApiVersion::mounted_routes);
+        let versions = self
+          .build(
+            Router::new(),
+            versions.collect(),
+            assets,
+            &metas,
+            limiter.as_ref(),
+          )
+          .with_state(ctx.clone());
+        let selector = Arc::new(selector);
+        let select = axum::middleware::from_fn(move |request, next| {
+          select_version(selector.clone(), request, next)
+        });
+        router.fallback_service(select.layer(versions))
+      }
+      None => router,
+    };
+    let router = middleware::apply(router, &ctx.config.server)?.layer(
+      axum::middleware::from_fn_with_state(ctx.shutdown.clone(), shutdown::track_in_flight),
+    );
+    // Outermost, so that every error answered carries the ID, even the
+    // `503` of a request aborted by the shutdown
+    Ok(router.layer(axum::middleware::from_fn(request_id)))
+  }
 
+  /// Adds `routes` to `router` with their layers, and `assets` or a JSON
+  /// `404` as the fallback, then the layers of the app and the rate limits.
+  fn build(
+    &self,
+    mut router: Router<AppContext>,
+    routes: Vec<Routes>,
+    assets: Option<StaticAssets>,
+    metas: &Arc<RouteMetas>,
+    limiter: Option<&Arc<RateLimiter>>,
+  ) -> Router<AppContext> {
+    let prefix = self.prefix.as_deref().unwrap_or("");
+    for route in routes {
+      let route_prefix = route.prefix.as_deref().unwrap_or("");
+      // Full paths rather than `nest`, so that the slashes are normalised
       let mut route_router = Router::new();
       for handler in route.handlers {
-        route_router = route_router.route(&handler.uri, handler.method);
+        let path = join_path(&[prefix, route_prefix, &handler.uri]);
+        route_router = route_router.route(&path, handler.method);
+      }
+      for layer in &route.layers {
+        route_router = layer.apply(route_router);
       }
+      router = router.merge(route_router);
+    }
 
-      router = router.nest(&full_prefix, route_router);
+    router = match assets {
+      Some(assets) => {
+        router.fallback(move |request: Request| async move { assets.serve(request).await })
+      }
+      None => router.fallback(|| async { Error::NotFound }),
+    };
+    // Under the layers of the app, so that they see the JSON answer
+    let allowed = metas.clone();
+    router = router.layer(axum::middleware::from_fn(move |request, next| {
+      method_not_allowed(allowed.clone(), request, next)
+    }));
+    for layer in &self.layers {
+      router = layer.apply(router);
+    }
+    // Before the layers of the app, which may be costly, such as logins
+    if let Some(limiter) = limiter {
+      let limiter = limiter.clone();
+      router = router.layer(axum::middleware::from_fn(move |request, next| {
+        rate_limit(limiter.clone(), request, next)
+      }));
     }
 
-    router.with_state(ctx.clone())
+    // Outermost, so that every layer above sees the metadata
+    let metas = metas.clone();
+    router.layer(axum::middleware::from_fn(move |request, next| {
+      insert_meta(metas.clone(), request, next)
+    }))
   }
 }
 
@@ -53,13 +337,123 @@ impl AppRoutes {
 pub struct Routes {
   pub prefix: Option<String>,
   pub handlers: Vec<Handler>,
+  /// Applied to every handler of the group, see [`Routes::layer`].
+  pub layers: Vec<RouteLayer>,
 }
 
 #[derive(Clone, Default, Debug)]
 pub struct Handler {
   pub uri: String,
-  pub method: axum::routing::MethodRouter<AppContext>,
-  pub actions: Vec<axum::http::Method>,
+  pub method: MethodRouter<AppContext>,
+  /// The methods answered by `method`, empty when it accepts any method.
+  pub actions: Vec<Method>,
+  pub meta: RouteMeta,
+}
+
+/// Describes a handler for the middleware, the documentation and the routes
+/// listing, see the builder methods of [`Routes`].
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct RouteMeta {
+  /// Overrides the name derived from the path.
+  pub name: Option<String>,
+  /// One line describing what the handler does.
+  pub summary: Option<String>,
+  pub tags: Vec<String>,
+  /// What the caller needs, `None` when the route is public.
+  pub auth: Option<AuthRequirement>,
+  pub deprecated: bool,
+  /// The JSON body expected, for the documentation.
+  pub request: Option<BodySchema>,
+  /// The answers worth documenting, by status.
+  pub responses: Vec<(StatusCode, Option<BodySchema>)>,
+  /// Limits the requests to the handler, see [`crate::config::rate_limit`].
+  pub rate_limit: Option<RateLimit>,
+  /// Left out of `server.rate_limit`, as the health probes are.
+  pub rate_limit_exempt: bool,
+}
+
+/// A JSON body in the OpenAPI document, built from a type deriving
+/// [`ToSchema`].
+#[derive(Clone)]
+pub struct BodySchema {
+  /// The name of the schema in the document components.
+  pub name: String,
+  /// A list of `name` rather than a single one.
+  pub list: bool,
+  /// The schema of `name`, then the schemas it refers to.
+  pub schemas: Vec<(String, RefOr<Schema>)>,
+}
+
+impl BodySchema {
+  /// A single `T`.
+  #[must_use]
+  pub fn of<T: ToSchema>() -> Self {
+    let mut schemas = vec![(T::name().to_string(), T::schema())];
+    T::schemas(&mut schemas);
+    Self {
+      name: T::name().to_string(),
+      list: false,
+      schemas,
+    }
+  }
+
+  /// A list of `T`.
+  #[must_use]
+  pub fn list_of<T: ToSchema>() -> Self {
+    Self {
+      list: true,
+      ..Self::of::<T>()
+    }
+  }
+}
+
+// Schemas are only compared by name, they are generated from the type
+impl PartialEq for BodySchema {
+  fn eq(&self, other: &Self) -> bool {
+    self.name == other.name && self.list == other.list
+  }
+}
+
+impl Eq for BodySchema {}
+
+impl std::fmt::Debug for BodySchema {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("BodySchema")
+      .field("name", &self.name)
+      .field("list", &self.list)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Access required by a route, enforced by the auth middleware.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthRequirement {
+  /// A user role, such as `manager`.
+  Role(String),
+  /// A token scope, such as `tasks:write`.
+  Scope(String),
+}
+
+impl std::fmt::Display for AuthRequirement {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Role(role) => write!(f, "role:{role}"),
+      Self::Scope(scope) => write!(f, "scope:{scope}"),
+    }
+  }
+}
+
+/// A registered handler, as listed by [`AppRoutes::collect`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteInfo {
+  /// The full path, prefixes included.
+  pub path: String,
+  /// The methods answered, empty when any method is.
+  pub methods: Vec<Method>,
+  /// Identifies the handler: [`RouteMeta::name`] when set, otherwise derived
+  /// from the path (`/api/tasks/{id}` is `api_tasks_id`).
+  pub name: String,
+  pub meta: RouteMeta,
 }
 
 impl Routes {
@@ -76,19 +470,719 @@ impl Routes {
     }
   }
 
-  /// Adds a new route handler.
-  pub fn add(mut self, uri: &str, method: axum::routing::MethodRouter<AppContext>) -> Self {
+  /// Adds a new route handler, built with [`crate::config::routing`] or
+  /// [`axum::routing`].
+  pub fn add(mut self, uri: &str, endpoint: impl Into<Endpoint>) -> Self {
+    let endpoint = endpoint.into();
     self.handlers.push(Handler {
       uri: uri.to_owned(),
-      method,
-      actions: vec![],
+      method: endpoint.router,
+      actions: endpoint.methods,
+      meta: RouteMeta::default(),
     });
     self
   }
 
+  /// Adds a new route handler wrapped in `layer`, which leaves the other
+  /// handlers of the group alone.
+  pub fn add_with_layer<L>(self, uri: &str, endpoint: impl Into<Endpoint>, layer: L) -> Self
+  where
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+  {
+    let mut routes = self.add(uri, endpoint);
+    if let Some(handler) = routes.handlers.last_mut() {
+      handler.method = std::mem::take(&mut handler.method).layer(layer);
+    }
+    routes
+  }
+
+  /// Wraps every handler of the group in `layer`, including the handlers
+  /// added after this call. The other groups are not affected.
+  ///
+  /// As with [`Router::layer`], the last layer added runs first.
+  #[must_use]
+  pub fn layer<L>(mut self, layer: L) -> Self
+  where
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+  {
+    self.layers.push(RouteLayer::new(layer));
+    self
+  }
+
   /// Sets a prefix for the config.
   pub fn prefix(mut self, uri: &str) -> Self {
     self.prefix = Some(uri.to_owned());
     self
   }
+
+  /// Names the last added handler.
+  ///
+  /// # Panics
+  ///
+  /// This and the other metadata setters panic when no handler was added
+  /// yet.
+  #[must_use]
+  #[track_caller]
+  pub fn name(mut self, name: &str) -> Self {
+    self.last_meta().name = Some(name.to_string());
+    self
+  }
+
+  /// Describes the last added handler in one line.
+  #[must_use]
+  #[track_caller]
+  pub fn summary(mut self, summary: &str) -> Self {
+    self.last_meta().summary = Some(summary.to_string());
+    self
+  }
+
+  /// Tags the last added handler, e.g. to group it in the documentation.
+  #[must_use]
+  #[track_caller]
+  pub fn tag(mut self, tag: &str) -> Self {
+    self.last_meta().tags.push(tag.to_string());
+    self
+  }
+
+  /// Restricts the last added handler to users with `role`.
+  #[must_use]
+  #[track_caller]
+  pub fn requires_role(mut self, role: &str) -> Self {
+    self.last_meta().auth = Some(AuthRequirement::Role(role.to_string()));
+    self
+  }
+
+  /// Restricts the last added handler to tokens granted `scope`.
+  #[must_use]
+  #[track_caller]
+  pub fn requires_scope(mut self, scope: &str) -> Self {
+    self.last_meta().auth = Some(AuthRequirement::Scope(scope.to_string()));
+    self
+  }
+
+  /// Marks the last added handler as deprecated.
+  #[must_use]
+  #[track_caller]
+  pub fn deprecated(mut self) -> Self {
+    self.last_meta().deprecated = true;
+    self
+  }
+
+  /// Documents the JSON body the last added handler expects.
+  #[must_use]
+  #[track_caller]
+  pub fn request<T: ToSchema>(mut self) -> Self {
+    self.last_meta().request = Some(BodySchema::of::<T>());
+    self
+  }
+
+  /// Documents an answer of the last added handler with a `T` body.
+  #[must_use]
+  #[track_caller]
+  pub fn response<T: ToSchema>(mut self, status: StatusCode) -> Self {
+    let body = BodySchema::of::<T>();
+    self.last_meta().responses.push((status, Some(body)));
+    self
+  }
+
+  /// Documents an answer of the last added handler with a list of `T`.
+  #[must_use]
+  #[track_caller]
+  pub fn response_list<T: ToSchema>(mut self, status: StatusCode) -> Self {
+    let body = BodySchema::list_of::<T>();
+    self.last_meta().responses.push((status, Some(body)));
+    self
+  }
+
+  /// Documents an answer of the last added handler without a documented
+  /// body.
+  #[must_use]
+  #[track_caller]
+  pub fn response_status(mut self, status: StatusCode) -> Self {
+    self.last_meta().responses.push((status, None));
+    self
+  }
+
+  /// Limits the requests to the last added handler, counted apart from
+  /// those to the other handlers, see [`crate::config::rate_limit`].
+  #[must_use]
+  #[track_caller]
+  pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+    self.last_meta().rate_limit = Some(limit);
+    self
+  }
+
+  /// Leaves the last added handler out of `server.rate_limit`, so that a
+  /// busy client does not get it refused, as with the health probes.
+  #[must_use]
+  #[track_caller]
+  pub fn rate_limit_exempt(mut self) -> Self {
+    self.last_meta().rate_limit_exempt = true;
+    self
+  }
+
+  #[track_caller]
+  fn last_meta(&mut self) -> &mut RouteMeta {
+    match self.handlers.last_mut() {
+      Some(handler) => &mut handler.meta,
+      None => panic!("add a handler before describing it"),
+    }
+  }
+}
+
+/// Fails on the first two handlers that axum would refuse to route
+/// together: the same path with overlapping methods, or paths differing by
+/// the names of their parameters only.
+fn check_conflicts(routes: &[RouteInfo]) -> Result<()> {
+  for (index, first) in routes.iter().enumerate() {
+    for second in &routes[index + 1..] {
+      let (first_shape, second_shape) = (path_shape(&first.path), path_shape(&second.path));
+      let reason = if first_shape != second_shape {
+        if !catch_all_overlaps(&first_shape, &second_shape) {
+          continue;
+        }
+        "a parameter and a catch-all take the same segment".to_string()
+      } else if first.path != second.path {
+        "their parameters are named differently".to_string()
+      } else if first.methods.is_empty() || second.methods.is_empty() {
+        "one of them answers any method".to_string()
+      } else if let Some(method) = first.methods.iter().find(|m| second.methods.contains(m)) {
+        format!("both answer {method}")
+      } else {
+        continue;
+      };
+      return Err(Error::RouteConflict {
+        first: describe_handler(first),
+        second: describe_handler(second),
+        reason,
+      });
+    }
+  }
+  Ok(())
+}
+
+/// The path with its parameters unnamed, as axum matches it.
+fn path_shape(path: &str) -> String {
+  path
+    .split('/')
+    .map(|segment| match segment.strip_prefix('{') {
+      Some(param) if param.starts_with('*') => "{*}",
+      Some(_) => "{}",
+      None => segment,
+    })
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+/// Where the shapes first differ, one has a parameter and the other a
+/// catch-all, which axum cannot tell apart.
+fn catch_all_overlaps(first: &str, second: &str) -> bool {
+  first
+    .split('/')
+    .zip(second.split('/'))
+    .find(|(first, second)| first != second)
+    .is_some_and(|segments| matches!(segments, ("{}", "{*}") | ("{*}", "{}")))
+}
+
+/// `name (GET, POST /path)`, or `name (ANY /path)`.
+fn describe_handler(route: &RouteInfo) -> String {
+  let methods = if route.methods.is_empty() {
+    "ANY".to_string()
+  } else {
+    route
+      .methods
+      .iter()
+      .map(Method::as_str)
+      .collect::<Vec<_>>()
+      .join(", ")
+  };
+  format!("{} ({methods} {})", route.name, route.path)
+}
+
+/// The metadata of every handler, by path.
+struct RouteMetas(HashMap<String, Vec<(Vec<Method>, RouteMeta)>>);
+
+impl RouteMetas {
+  fn new(routes: &[RouteInfo]) -> Self {
+    let mut metas: HashMap<_, Vec<_>> = HashMap::new();
+    for route in routes {
+      metas
+        .entry(route.path.clone())
+        .or_default()
+        .push((route.methods.clone(), route.meta.clone()));
+    }
+    Self(metas)
+  }
+
+  fn get(&self, path: &str, method: &Method) -> Option<&RouteMeta> {
+    let handlers = self.0.get(&join_path(&[path]))?;
+    let answers =
+      |methods: &Vec<Method>, method: &Method| methods.is_empty() || methods.contains(method);
+    handlers
+      .iter()
+      .find(|(methods, _)| answers(methods, method))
+      // axum answers HEAD with the GET handler
+      .or_else(|| {
+        (method == Method::HEAD)
+          .then(|| {
+            handlers
+              .iter()
+              .find(|(methods, _)| answers(methods, &Method::GET))
+          })
+          .flatten()
+      })
+      .map(|(_, meta)| meta)
+  }
+
+  /// The methods answered at `path`, HEAD included when GET is. `None` when
+  /// a handler answers any method.
+  fn allowed(&self, path: &str) -> Option<Vec<Method>> {
+    let mut allowed = Vec::new();
+    for (methods, _) in self.0.get(&join_path(&[path]))? {
+      if methods.is_empty() {
+        return None;
+      }
+      for method in methods {
+        if !allowed.contains(method) {
+          allowed.push(method.clone());
+        }
+      }
+    }
+    if allowed.contains(&Method::GET) && !allowed.contains(&Method::HEAD) {
+      allowed.push(Method::HEAD);
+    }
+    Some(allowed)
+  }
+}
+
+async fn insert_meta(
+  metas: Arc<RouteMetas>,
+  mut request: Request,
+  next: Next,
+) -> impl IntoResponse {
+  let meta = request
+    .extensions()
+    .get::<MatchedPath>()
+    .and_then(|path| metas.get(path.as_str(), request.method()))
+    .cloned();
+  if let Some(meta) = meta {
+    request.extensions_mut().insert(meta);
+  }
+  next.run(request).await
+}
+
+/// Answers the `405` of axum, sent when the path matches but not the method,
+/// with an [`Error::MethodNotAllowed`].
+async fn method_not_allowed(metas: Arc<RouteMetas>, request: Request, next: Next) -> Response {
+  let allowed = request
+    .extensions()
+    .get::<MatchedPath>()
+    .and_then(|path| metas.allowed(path.as_str()))
+    .filter(|allowed| !allowed.contains(request.method()));
+  let response = next.run(request).await;
+  match allowed {
+    Some(allowed) if response.status() == StatusCode::METHOD_NOT_ALLOWED => {
+      Error::MethodNotAllowed(allowed).into_response()
+    }
+    _ => response,
+  }
+}
+
+/// A middleware layer kept by [`Routes::layer`] or [`AppRoutes::layer`]
+/// until the router is built.
+#[derive(Clone)]
+pub struct RouteLayer(Arc<dyn Fn(Router<AppContext>) -> Router<AppContext> + Send + Sync>);
+
+impl RouteLayer {
+  fn new<L>(layer: L) -> Self
+  where
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+  {
+    Self(Arc::new(move |router| router.layer(layer.clone())))
+  }
+
+  fn apply(&self, router: Router<AppContext>) -> Router<AppContext> {
+    (self.0)(router)
+  }
+}
+
+impl std::fmt::Debug for RouteLayer {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("RouteLayer")
+  }
+}
+
+/// Joins path segments with single slashes, without a trailing one.
+pub(crate) fn join_path(segments: &[&str]) -> String {
+  let path = segments
+    .iter()
+    .flat_map(|segment| segment.split('/'))
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join("/");
+  format!("/{path}")
+}
+
+fn default_name(path: &str) -> String {
+  let name = path
+    .split('/')
+    .map(|part| part.trim_matches(|c| c == '{' || c == '}' || c == '*'))
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join("_");
+  if name.is_empty() {
+    "root".to_string()
+  } else {
+    name
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::routing::{any, delete, get, patch, post};
+  use crate::config::testing::{handler, offline_context};
+  use crate::config::Config;
+  use axum::body::Body;
+  use axum::http::header::ALLOW;
+  use axum::http::HeaderValue;
+  use http_body_util::BodyExt;
+  use tower::util::MapResponseLayer;
+  use tower::ServiceExt;
+
+  /// Appends `name` to the `x-tags` response header.
+  fn tag(name: &'static str) -> MapResponseLayer<impl Fn(Response) -> Response + Clone> {
+    MapResponseLayer::new(move |mut response: Response| {
+      let tags = match response.headers().get("x-tags") {
+        Some(tags) => format!("{},{name}", tags.to_str().unwrap()),
+        None => name.to_string(),
+      };
+      response
+        .headers_mut()
+        .insert("x-tags", HeaderValue::from_str(&tags).unwrap());
+      response
+    })
+  }
+
+  async fn tags(router: &Router, method: Method, uri: &str) -> (StatusCode, Option<String>) {
+    let request = Request::builder()
+      .method(method)
+      .uri(uri)
+      .body(Body::empty())
+      .unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    let tags = response
+      .headers()
+      .get("x-tags")
+      .map(|tags| tags.to_str().unwrap().to_string());
+    (response.status(), tags)
+  }
+
+  #[tokio::test]
+  async fn layers_apply_to_their_own_routes() {
+    let ctx = offline_context(Config::default()).await;
+    let router = AppRoutes::empty()
+      .add_route(Routes::at("/_health").add("/", get(handler)))
+      .add_route(
+        Routes::at("/api/tasks")
+          .layer(tag("group"))
+          .add("/", get(handler))
+          .add_with_layer("/{id}", get(handler), tag("handler")),
+      )
+      .layer(tag("app"))
+      .into_router(&ctx)
+      .unwrap();
+
+    assert_eq!(
+      tags(&router, Method::GET, "/_health").await,
+      (StatusCode::OK, Some("app".to_string()))
+    );
+    assert_eq!(
+      tags(&router, Method::GET, "/api/tasks").await,
+      (StatusCode::OK, Some("group,app".to_string()))
+    );
+    assert_eq!(
+      tags(&router, Method::GET, "/api/tasks/1").await,
+      (StatusCode::OK, Some("handler,group,app".to_string()))
+    );
+    assert_eq!(
+      tags(&router, Method::GET, "/unknown").await,
+      (StatusCode::NOT_FOUND, Some("app".to_string()))
+    );
+  }
+
+  #[tokio::test]
+  async fn answers_unknown_paths_and_methods_in_json() {
+    let ctx = offline_context(Config::default()).await;
+    let router = AppRoutes::empty()
+      .add_route(
+        Routes::at("/api/tasks")
+          .add("/{id}", get(handler))
+          .add("/{id}", patch(handler))
+          .add("/any", any(handler)),
+      )
+      .layer(tag("app"))
+      .into_router(&ctx)
+      .unwrap();
+    let send = |method: Method, uri: &'static str| {
+      let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+      router.clone().oneshot(request)
+    };
+
+    let response = send(Method::DELETE, "/api/tasks/1").await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()[ALLOW], "GET, PATCH, HEAD");
+    assert_eq!(response.headers()["x-tags"], "app");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "method_not_allowed");
+
+    let response = send(Method::GET, "/api/unknown").await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "not_found");
+
+    let response = send(Method::PATCH, "/api/tasks/1").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(Method::DELETE, "/api/tasks/any").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+
+  #[tokio::test]
+  async fn middleware_sees_the_route_metadata() {
+    let ctx = offline_context(Config::default()).await;
+    let routes = Routes::at("/api/tasks")
+      .add("/", get(handler))
+      .name("list_tasks")
+      .tag("tasks")
+      .add("/", post(handler))
+      .name("create_task")
+      .requires_role("manager")
+      .add("/{id}", get(handler))
+      .requires_scope("tasks:read")
+      .deprecated();
+    let app_routes = AppRoutes::empty().add_route(routes);
+
+    let names: Vec<_> = app_routes.collect().into_iter().map(|r| r.name).collect();
+    assert_eq!(names, ["list_tasks", "create_task", "api_tasks_id"]);
+
+    let router = app_routes
+      .layer(axum::middleware::from_fn(describe_route))
+      .into_router(&ctx)
+      .unwrap();
+    let described = |method: Method, uri: &'static str| {
+      let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+      let router = router.clone();
+      async move {
+        let response = router.oneshot(request).await.unwrap();
+        response.headers()["x-route"].to_str().unwrap().to_string()
+      }
+    };
+    assert_eq!(
+      described(Method::GET, "/api/tasks").await,
+      "Some(\"list_tasks\") None false"
+    );
+    assert_eq!(
+      described(Method::HEAD, "/api/tasks").await,
+      "Some(\"list_tasks\") None false"
+    );
+    assert_eq!(
+      described(Method::POST, "/api/tasks").await,
+      "Some(\"create_task\") Some(Role(\"manager\")) false"
+    );
+    assert_eq!(
+      described(Method::GET, "/api/tasks/1").await,
+      "None Some(Scope(\"tasks:read\")) true"
+    );
+    assert_eq!(described(Method::GET, "/unknown").await, "none");
+  }
+
+  /// Reports the metadata of the route in the `x-route` response header.
+  async fn describe_route(request: Request, next: Next) -> Response {
+    let description = request
+      .extensions()
+      .get::<RouteMeta>()
+      .map_or("none".to_string(), |meta| {
+        format!("{:?} {:?} {}", meta.name, meta.auth, meta.deprecated)
+      });
+    let mut response = next.run(request).await;
+    response
+      .headers_mut()
+      .insert("x-route", HeaderValue::from_str(&description).unwrap());
+    response
+  }
+
+  #[tokio::test]
+  async fn refuses_conflicting_routes() {
+    let ctx = offline_context(Config::default()).await;
+    let conflict = |routes: AppRoutes| match routes.into_router(&ctx) {
+      Err(err) => err.to_string(),
+      Ok(_) => "no conflict".to_string(),
+    };
+
+    assert_eq!(
+      conflict(
+        AppRoutes::empty()
+          .add_route(
+            Routes::at("/api/tasks")
+              .add("/{id}", get(handler))
+              .name("get_task")
+          )
+          .add_route(
+            Routes::at("/api/tasks/")
+              .add("{id}/", get(handler).delete(handler))
+              .name("show_task")
+          )
+      ),
+      "route `get_task (GET /api/tasks/{id})` conflicts with `show_task (GET, DELETE \
+       /api/tasks/{id})`: both answer GET"
+    );
+    assert_eq!(
+      conflict(
+        AppRoutes::empty()
+          .add_route(Routes::at("/api/tasks").add("/{id}", get(handler)))
+          .add_route(Routes::at("/api/tasks").add("/{task_id}", post(handler)))
+      ),
+      "route `api_tasks_id (GET /api/tasks/{id})` conflicts with `api_tasks_task_id (POST \
+       /api/tasks/{task_id})`: their parameters are named differently"
+    );
+    assert_eq!(
+      conflict(
+        AppRoutes::empty()
+          .add_route(Routes::at("/api/tasks").add("/", any(handler)))
+          .add_route(Routes::at("/api/tasks").add("/", post(handler)))
+      ),
+      "route `api_tasks (ANY /api/tasks)` conflicts with `api_tasks (POST /api/tasks)`: one of \
+       them answers any method"
+    );
+    assert_eq!(
+      conflict(
+        AppRoutes::empty()
+          .add_route(Routes::at("/files").add("/{id}/meta", get(handler)))
+          .add_route(Routes::at("/files").add("/{*path}", post(handler)))
+      ),
+      "route `files_id_meta (GET /files/{id}/meta)` conflicts with `files_path (POST \
+       /files/{*path})`: a parameter and a catch-all take the same segment"
+    );
+
+    // Other methods on the same path, whatever the slashes
+    let router = AppRoutes::empty()
+      .prefix("/api/")
+      .add_route(Routes::at("tasks/").add("/{id}", get(handler)))
+      .add_route(Routes::at("/tasks").add("{id}/", delete(handler)))
+      .into_router(&ctx)
+      .unwrap();
+    for method in [Method::GET, Method::DELETE] {
+      let request = Request::builder()
+        .method(method)
+        .uri("/api/tasks/1")
+        .body(Body::empty())
+        .unwrap();
+      let response = router.clone().oneshot(request).await.unwrap();
+      assert_eq!(response.status(), StatusCode::OK);
+    }
+  }
+
+  #[tokio::test]
+  async fn refuses_routes_taken_by_swagger_ui() {
+    let ctx = offline_context(Config::default()).await;
+    let conflict = |path: &str| {
+      AppRoutes::with_default_routes()
+        .add_route(Routes::at("/api/docs").add(path, get(handler)).name("page"))
+        .into_router(&ctx)
+        .err()
+        .map(|err| err.to_string())
+    };
+
+    assert_eq!(
+      conflict("/{page}").as_deref(),
+      Some(
+        "route `page (GET /api/docs/{page})` conflicts with `swagger_ui (GET \
+         /api/docs/{*rest})`: a parameter and a catch-all take the same segment"
+      )
+    );
+    assert_eq!(
+      conflict("/").as_deref(),
+      Some(
+        "route `page (GET /api/docs)` conflicts with `swagger_ui (GET /api/docs)`: both answer GET"
+      )
+    );
+    assert_eq!(conflict("/index.html"), None);
+    // Free once the documentation is left out
+    let routes = AppRoutes::with_default_routes()
+      .without_docs()
+      .add_route(Routes::at("/api/docs").add("/{page}", get(handler)));
+    assert!(routes.into_router(&ctx).is_ok());
+  }
+
+  #[test]
+  fn layered_handlers_keep_their_methods() {
+    let routes = Routes::new().add_with_layer("/", get(handler).post(handler), tag("handler"));
+    assert_eq!(routes.handlers[0].actions, [Method::GET, Method::POST]);
+  }
+
+  #[test]
+  fn records_the_methods_of_each_handler() {
+    let routes = Routes::at("/tasks")
+      .add("/", get(handler).post(handler))
+      .add("/{id}", get(handler).patch(handler).delete(handler))
+      .add("/any", any(handler));
+
+    let actions: Vec<_> = routes.handlers.iter().map(|h| h.actions.clone()).collect();
+    assert_eq!(
+      actions,
+      vec![
+        vec![Method::GET, Method::POST],
+        vec![Method::GET, Method::PATCH, Method::DELETE],
+        vec![],
+      ]
+    );
+  }
+
+  #[test]
+  fn can_collect_routes() {
+    let app_routes = AppRoutes::empty()
+      .prefix("/api/")
+      .add_route(Routes::at("/tasks").add("/", get(handler)))
+      .add_route(Routes::new().add("/{id}/", get(handler).put(handler)));
+
+    assert_eq!(
+      app_routes.collect(),
+      vec![
+        RouteInfo {
+          path: "/api/tasks".to_string(),
+          methods: vec![Method::GET],
+          name: "api_tasks".to_string(),
+          meta: RouteMeta::default(),
+        },
+        RouteInfo {
+          path: "/api/{id}".to_string(),
+          methods: vec![Method::GET, Method::PUT],
+          name: "api_id".to_string(),
+          meta: RouteMeta::default(),
+        },
+      ]
+    );
+  }
 }
@@ -0,0 +1,201 @@
+//! # Routing
+//!
+//! The handlers given to [`Routes::add`](crate::config::routes_config::Routes::add),
+//! built like those of [`axum::routing`] but recording the methods they
+//! answer, which axum keeps to itself. The methods make the `Allow` header,
+//! the route conflicts, the OpenAPI document and the routes listing.
+//!
+//! A [`MethodRouter`] of axum is accepted as well: its methods are read from
+//! the `Allow` header it keeps for its `405` answers.
+//!
+//! ```rust
+//! use pos_rust_local_backend::config::routes_config::Routes;
+//! use pos_rust_local_backend::config::routing::get;
+//!
+//! async fn list_tasks() {}
+//! async fn create_task() {}
+//!
+//! let routes = Routes::at("/api/tasks").add("/", get(list_tasks).post(create_task));
+//! ```
+use crate::config::app_context::AppContext;
+use axum::handler::Handler;
+use axum::http::Method;
+use axum::routing::MethodRouter;
+
+/// A [`MethodRouter`] and the methods it answers.
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+  pub(crate) router: MethodRouter<AppContext>,
+  /// Empty when any method is answered, see [`any`].
+  pub(crate) methods: Vec<Method>,
+}
+
+impl Endpoint {
+  /// Wraps a [`MethodRouter`] built with axum, such as a service, answering
+  /// exactly `methods`.
+  ///
+  /// # Panics
+  ///
+  /// Panics when `router` does not answer exactly `methods`, `HEAD` aside
+  /// since axum answers it with the `GET` handler.
+  #[must_use]
+  pub fn on(methods: &[Method], router: MethodRouter<AppContext>) -> Self {
+    let endpoint = Self {
+      router,
+      methods: methods.to_vec(),
+    };
+    let answered = answered_methods(&endpoint.router);
+    assert_eq!(
+      without_head(endpoint.methods.clone()),
+      without_head(answered.clone()),
+      "the router answers {answered:?}, not {methods:?}",
+    );
+    endpoint
+  }
+
+  /// The methods answered, empty when any method is.
+  #[must_use]
+  pub fn methods(&self) -> &[Method] {
+    &self.methods
+  }
+
+  fn add_method(&mut self, method: Method) {
+    // Already answered when any method is
+    if !self.methods.is_empty() && !self.methods.contains(&method) {
+      self.methods.push(method);
+    }
+  }
+}
+
+impl From<MethodRouter<AppContext>> for Endpoint {
+  /// Records the methods `router` answers.
+  fn from(router: MethodRouter<AppContext>) -> Self {
+    let methods = answered_methods(&router);
+    let implicit_head = methods.contains(&Method::GET);
+    Self {
+      router,
+      methods: methods
+        .into_iter()
+        .filter(|method| !(implicit_head && method == Method::HEAD))
+        .collect(),
+    }
+  }
+}
+
+/// The methods of the `Allow` header `router` keeps for its `405` answers,
+/// `HEAD` included when `GET` is, and empty when any method is answered.
+///
+/// axum only exposes the header through the `Debug` output of the router.
+fn answered_methods(router: &MethodRouter<AppContext>) -> Vec<Method> {
+  let debug = format!("{router:?}");
+  let Some(allow) = debug
+    .rsplit_once("allow_header: Bytes(b\"")
+    .and_then(|(_, allow)| allow.split_once('"'))
+  else {
+    return vec![];
+  };
+  allow
+    .0
+    .split(',')
+    .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+    .collect()
+}
+
+/// `methods` sorted, without `HEAD` when `GET` answers it.
+fn without_head(mut methods: Vec<Method>) -> Vec<Method> {
+  if methods.contains(&Method::GET) {
+    methods.retain(|method| method != Method::HEAD);
+  }
+  methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+  methods
+}
+
+/// Routes every method to `handler`, see [`axum::routing::any`].
+pub fn any<H, T>(handler: H) -> Endpoint
+where
+  H: Handler<T, AppContext>,
+  T: 'static,
+{
+  Endpoint::on(&[], axum::routing::any(handler))
+}
+
+macro_rules! methods {
+  ($($name:ident => $method:ident),* $(,)?) => {
+    $(
+      #[doc = concat!("Routes `", stringify!($method), "` requests to `handler`, see [`axum::routing::", stringify!($name), "`].")]
+      pub fn $name<H, T>(handler: H) -> Endpoint
+      where
+        H: Handler<T, AppContext>,
+        T: 'static,
+      {
+        Endpoint::on(&[Method::$method], axum::routing::$name(handler))
+      }
+    )*
+
+    impl Endpoint {
+      $(
+        #[doc = concat!("Also routes `", stringify!($method), "` requests to `handler`.")]
+        #[must_use]
+        pub fn $name<H, T>(mut self, handler: H) -> Self
+        where
+          H: Handler<T, AppContext>,
+          T: 'static,
+        {
+          self.router = self.router.$name(handler);
+          self.add_method(Method::$method);
+          self
+        }
+      )*
+    }
+  };
+}
+
+methods! {
+  get => GET,
+  post => POST,
+  put => PUT,
+  patch => PATCH,
+  delete => DELETE,
+  head => HEAD,
+  options => OPTIONS,
+  trace => TRACE,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::testing::handler;
+
+  #[test]
+  fn records_the_methods() {
+    assert_eq!(
+      get(handler).post(handler).methods(),
+      [Method::GET, Method::POST]
+    );
+    assert!(any(handler).post(handler).methods().is_empty());
+    assert_eq!(
+      Endpoint::on(&[Method::PUT], axum::routing::put(handler)).methods(),
+      [Method::PUT]
+    );
+  }
+
+  #[test]
+  fn reads_the_methods_of_axum_routers() {
+    let endpoint = Endpoint::from(axum::routing::get(handler).delete(handler));
+    assert_eq!(endpoint.methods(), [Method::GET, Method::DELETE]);
+    let endpoint = Endpoint::from(axum::routing::head(handler).post(handler));
+    assert_eq!(endpoint.methods(), [Method::HEAD, Method::POST]);
+    assert!(Endpoint::from(axum::routing::any(handler))
+      .methods()
+      .is_empty());
+    assert!(Endpoint::on(&[], axum::routing::any(handler))
+      .methods()
+      .is_empty());
+  }
+
+  #[test]
+  #[should_panic(expected = "the router answers")]
+  fn refuses_methods_the_router_does_not_answer() {
+    let _ = Endpoint::on(&[Method::POST], axum::routing::get(handler));
+  }
+}
@@ -0,0 +1,455 @@
+//! # Static assets
+//!
+//! Serves the built POS frontend next to the API, answering the requests no
+//! route matched. The files come from a directory, or are embedded in the
+//! binary at compile time with the `embed-assets` feature, so that a till
+//! runs a single executable.
+//!
+//! - `Cache-Control`: files under the `immutable` prefixes (`/assets` by
+//!   default, where bundlers put the files with a content hash in their name)
+//!   are cached for `max_age` seconds; every other file is revalidated with
+//!   its `ETag`, so a new `index.html` is picked up right away;
+//! - a `.br` or `.gz` file next to the requested one is sent instead when the
+//!   client accepts that encoding;
+//! - unknown paths that look like a page of the frontend are answered with
+//!   `index.html`, so the frontend router handles them. The `exclude`
+//!   prefixes (`/api` by default) never are, a mistyped API call gets a
+//!   `404`.
+//!
+//! ```toml
+//! [server.assets]
+//! dir = "frontend/dist"
+//! ```
+use crate::config::routes_config::join_path;
+use crate::config::Assets;
+use crate::Error;
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{
+  ACCEPT, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+  IF_NONE_MATCH, VARY,
+};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio_util::io::ReaderStream;
+
+/// Precompressed variants, in order of preference.
+const ENCODINGS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+const INDEX: &str = "index.html";
+
+/// A file to serve.
+#[derive(Debug)]
+pub struct Asset {
+  pub data: AssetData,
+  /// Size of the data, in bytes.
+  pub len: u64,
+  /// Quoted entity tag, changing with the content.
+  pub etag: String,
+}
+
+/// The content of an [`Asset`].
+#[derive(Debug)]
+pub enum AssetData {
+  /// Already in memory, like the embedded files.
+  Bytes(Bytes),
+  /// Read as it is sent, so a large file is never held in memory.
+  File(tokio::fs::File),
+}
+
+/// Where the assets are read from.
+#[async_trait]
+pub trait AssetSource: Send + Sync + 'static {
+  /// The file at `path`, relative to the root of the assets.
+  async fn get(&self, path: &str) -> Option<Asset>;
+}
+
+/// Files read from a directory on every request, so a new frontend build is
+/// served without a restart. They are streamed from disk.
+#[derive(Clone, Debug)]
+pub struct Dir(pub PathBuf);
+
+#[async_trait]
+impl AssetSource for Dir {
+  async fn get(&self, path: &str) -> Option<Asset> {
+    let file = tokio::fs::File::open(self.0.join(path)).await.ok()?;
+    // Of the file opened, which a new build may replace meanwhile
+    let metadata = file.metadata().await.ok()?;
+    if !metadata.is_file() {
+      return None;
+    }
+    let modified = metadata
+      .modified()
+      .ok()
+      .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+      .unwrap_or_default();
+    Some(Asset {
+      etag: format!(
+        "W/\"{:x}-{:x}{:x}\"",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+      ),
+      data: AssetData::File(file),
+      len: metadata.len(),
+    })
+  }
+}
+
+/// Files embedded at compile time by a [`rust_embed::RustEmbed`] type.
+#[cfg(feature = "embed-assets")]
+pub struct Embedded<E>(std::marker::PhantomData<E>);
+
+#[cfg(feature = "embed-assets")]
+#[async_trait]
+impl<E: rust_embed::RustEmbed + Send + Sync + 'static> AssetSource for Embedded<E> {
+  async fn get(&self, path: &str) -> Option<Asset> {
+    let file = E::get(path)?;
+    let hash: String = file
+      .metadata
+      .sha256_hash()
+      .iter()
+      .map(|byte| format!("{byte:02x}"))
+      .collect();
+    let data = match file.data {
+      std::borrow::Cow::Borrowed(data) => Bytes::from_static(data),
+      std::borrow::Cow::Owned(data) => Bytes::from(data),
+    };
+    Some(Asset {
+      len: data.len() as u64,
+      data: AssetData::Bytes(data),
+      etag: format!("\"{hash}\""),
+    })
+  }
+}
+
+/// Serves an [`AssetSource`], see the [module documentation](self).
+#[derive(Clone)]
+pub struct StaticAssets {
+  source: Arc<dyn AssetSource>,
+  options: Assets,
+}
+
+impl StaticAssets {
+  /// Serves `source` with the default options.
+  pub fn new(source: impl AssetSource) -> Self {
+    Self {
+      source: Arc::new(source),
+      options: Assets::default(),
+    }
+  }
+
+  /// Serves the files of `dir`.
+  pub fn dir(dir: impl Into<PathBuf>) -> Self {
+    Self::new(Dir(dir.into()))
+  }
+
+  /// Serves the files embedded by `E`:
+  ///
+  /// ```rust,ignore
+  /// #[derive(rust_embed::RustEmbed)]
+  /// #[folder = "frontend/dist"]
+  /// struct Frontend;
+  ///
+  /// let assets = StaticAssets::embedded::<Frontend>();
+  /// ```
+  #[cfg(feature = "embed-assets")]
+  pub fn embedded<E: rust_embed::RustEmbed + Send + Sync + 'static>() -> Self {
+    Self::new(Embedded::<E>(std::marker::PhantomData))
+  }
+
+  /// Serves the directory of `config` with its options.
+  pub fn from_config(config: &Assets) -> Self {
+    Self::dir(&config.dir).options(config)
+  }
+
+  /// Sets the options of `config`, but the directory.
+  #[must_use]
+  pub fn options(mut self, config: &Assets) -> Self {
+    self.options = config.clone();
+    self
+  }
+
+  /// The excluded paths under the prefix of the app.
+  pub(crate) fn excluding_under(mut self, prefix: &str) -> Self {
+    for path in &mut self.options.exclude {
+      *path = join_path(&[prefix, path]);
+    }
+    self
+  }
+
+  /// Answers `request` with an asset, `index.html`, or `404 Not Found`.
+  pub async fn serve(&self, request: Request) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD)
+      || self.is_excluded(request.uri().path())
+    {
+      return Error::NotFound.into_response();
+    }
+    let Some(mut path) = relative_path(request.uri().path()) else {
+      return Error::NotFound.into_response();
+    };
+    if path.is_empty() || path.ends_with('/') {
+      path.push_str(INDEX);
+    }
+
+    let headers = request.headers();
+    let mut found = self.find(&path, headers).await;
+    if found.is_none() && self.options.spa_fallback && is_page(&path, headers) {
+      path = INDEX.to_string();
+      found = self.find(&path, headers).await;
+    }
+    let Some((asset, encoding)) = found else {
+      return Error::NotFound.into_response();
+    };
+
+    let mut response = if is_fresh(headers, &asset.etag) {
+      StatusCode::NOT_MODIFIED.into_response()
+    } else {
+      let mime = mime_guess::from_path(&path).first_or_octet_stream();
+      let mut response = match asset.data {
+        _ if request.method() == Method::HEAD => ().into_response(),
+        AssetData::Bytes(data) => data.into_response(),
+        AssetData::File(file) => Body::from_stream(ReaderStream::new(file)).into_response(),
+      };
+      response
+        .headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from(asset.len));
+      let headers = response.headers_mut();
+      if let Ok(mime) = HeaderValue::from_str(mime.as_ref()) {
+        headers.insert(CONTENT_TYPE, mime);
+      }
+      if let Some(encoding) = encoding {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+      }
+      response
+    };
+
+    let headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(&asset.etag) {
+      headers.insert(ETAG, etag);
+    }
+    headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    let cache_control = if path != INDEX && self.is_immutable(&path) {
+      format!("public, max-age={}, immutable", self.options.max_age)
+    } else {
+      "no-cache".to_string()
+    };
+    if let Ok(cache_control) = HeaderValue::from_str(&cache_control) {
+      headers.insert(CACHE_CONTROL, cache_control);
+    }
+    response
+  }
+
+  /// The precompressed variant of `path` the client accepts, or `path`.
+  async fn find(&self, path: &str, headers: &HeaderMap) -> Option<(Asset, Option<&'static str>)> {
+    for (encoding, extension) in ENCODINGS {
+      if accepts_encoding(headers, encoding) {
+        if let Some(asset) = self.source.get(&format!("{path}{extension}")).await {
+          return Some((asset, Some(encoding)));
+        }
+      }
+    }
+    self.source.get(path).await.map(|asset| (asset, None))
+  }
+
+  fn is_excluded(&self, path: &str) -> bool {
+    self
+      .options
+      .exclude
+      .iter()
+      .any(|prefix| has_prefix(path, prefix))
+  }
+
+  /// `path` is relative, the prefixes start with a slash.
+  fn is_immutable(&self, path: &str) -> bool {
+    let path = format!("/{path}");
+    self
+      .options
+      .immutable
+      .iter()
+      .any(|prefix| has_prefix(&path, prefix))
+  }
+}
+
+impl std::fmt::Debug for StaticAssets {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("StaticAssets")
+      .field("options", &self.options)
+      .finish_non_exhaustive()
+  }
+}
+
+/// `prefix` as whole segments: `/api` covers `/api/tasks`, not `/apidocs`.
+fn has_prefix(path: &str, prefix: &str) -> bool {
+  let prefix = prefix.trim_end_matches('/');
+  path
+    .strip_prefix(prefix)
+    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The decoded path without its leading slash, `None` when it could leave
+/// the root of the assets.
+fn relative_path(path: &str) -> Option<String> {
+  let path = percent_encoding::percent_decode_str(path)
+    .decode_utf8()
+    .ok()?;
+  let path = path.trim_start_matches('/');
+  let escapes = path
+    .split('/')
+    .any(|segment| segment == ".." || segment.contains(['\\', '\0']));
+  (!escapes).then(|| path.to_string())
+}
+
+/// Paths without an extension are frontend routes, as are the navigations of
+/// the browser.
+fn is_page(path: &str, headers: &HeaderMap) -> bool {
+  let file = path.rsplit('/').next().unwrap_or(path);
+  let accepts_html = headers
+    .get(ACCEPT)
+    .and_then(|accept| accept.to_str().ok())
+    .is_some_and(|accept| accept.contains("text/html"));
+  !file.contains('.') || accepts_html
+}
+
+fn accepts_encoding(headers: &HeaderMap, encoding: &str) -> bool {
+  headers
+    .get_all(ACCEPT_ENCODING)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|item| {
+      let mut parts = item.split(';').map(str::trim);
+      let name = parts.next().unwrap_or_default();
+      let refused = parts.any(|param| {
+        param
+          .strip_prefix("q=")
+          .and_then(|q| q.parse::<f32>().ok())
+          .is_some_and(|q| q == 0.0)
+      });
+      name.eq_ignore_ascii_case(encoding) && !refused
+    })
+}
+
+/// The client already has this version, per `If-None-Match`.
+fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+  let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+  headers
+    .get(IF_NONE_MATCH)
+    .and_then(|value| value.to_str().ok())
+    .is_some_and(|value| {
+      value
+        .split(',')
+        .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::body::Body;
+  use http_body_util::BodyExt;
+
+  /// The directory is removed once the returned [`tempfile::TempDir`] drops.
+  fn assets() -> (StaticAssets, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("index.html"), "<html>till</html>").unwrap();
+    std::fs::write(root.join("assets/app-4f2a9c1b.js"), "console.log(1)").unwrap();
+    std::fs::write(root.join("assets/app-4f2a9c1b.js.br"), "brotli").unwrap();
+    std::fs::write(root.join("favicon.ico"), "icon").unwrap();
+    (StaticAssets::dir(root), dir)
+  }
+
+  async fn get(
+    assets: &StaticAssets,
+    uri: &str,
+    headers: &[(&str, &str)],
+  ) -> (StatusCode, HeaderMap, String) {
+    let mut request = Request::builder().uri(uri);
+    for (name, value) in headers {
+      request = request.header(*name, *value);
+    }
+    let response = assets.serve(request.body(Body::empty()).unwrap()).await;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, String::from_utf8_lossy(&body).to_string())
+  }
+
+  #[tokio::test]
+  async fn serves_files_with_cache_headers() {
+    let (assets, _dir) = assets();
+
+    let (status, headers, body) = get(&assets, "/assets/app-4f2a9c1b.js", &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "console.log(1)");
+    assert_eq!(headers[CONTENT_TYPE], "text/javascript");
+    assert_eq!(headers[CONTENT_LENGTH], "14");
+    assert_eq!(
+      headers[CACHE_CONTROL],
+      "public, max-age=31536000, immutable"
+    );
+
+    let (_, headers, body) = get(
+      &assets,
+      "/assets/app-4f2a9c1b.js",
+      &[("accept-encoding", "gzip, br")],
+    )
+    .await;
+    assert_eq!(body, "brotli");
+    assert_eq!(headers[CONTENT_ENCODING], "br");
+    assert_eq!(headers[CONTENT_TYPE], "text/javascript");
+    let (_, _, body) = get(
+      &assets,
+      "/assets/app-4f2a9c1b.js",
+      &[("accept-encoding", "br;q=0")],
+    )
+    .await;
+    assert_eq!(body, "console.log(1)");
+
+    let (_, headers, body) = get(&assets, "/", &[]).await;
+    assert_eq!(body, "<html>till</html>");
+    assert_eq!(headers[CACHE_CONTROL], "no-cache");
+    let etag = headers[ETAG].to_str().unwrap();
+    let (status, _, body) = get(&assets, "/", &[("if-none-match", etag)]).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(body, "");
+  }
+
+  #[tokio::test]
+  async fn falls_back_to_the_index_for_frontend_routes() {
+    let (assets, _dir) = assets();
+
+    let (status, _, body) = get(&assets, "/orders/42", &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "<html>till</html>");
+    let (_, _, body) = get(&assets, "/receipt.v2", &[("accept", "text/html")]).await;
+    assert_eq!(body, "<html>till</html>");
+
+    for uri in [
+      "/assets/missing.js",
+      "/api/unknown",
+      "/api",
+      "/../secret",
+      "/%2e%2e/secret",
+    ] {
+      let (status, _, _) = get(&assets, uri, &[]).await;
+      assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+    }
+
+    let (status, _, _) = get(&assets, "/orders/42", &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let assets = assets.options(&Assets {
+      spa_fallback: false,
+      ..Assets::default()
+    });
+    let (status, _, _) = get(&assets, "/orders/42", &[]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+  }
+}
@@ -0,0 +1,60 @@
+//! Helpers shared by the tests of the router and its middleware.
+use crate::config::app_context::AppContext;
+use crate::config::routes_config::AppRoutes;
+use crate::config::Config;
+use crate::Result;
+use axum::extract::Request;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use sea_orm::DatabaseConnection;
+use serde_json::Value;
+use tower::ServiceExt;
+
+/// A handler answering `200` with an empty body.
+pub(crate) async fn handler() {}
+
+/// A context without a database, for the tests of what does not query it,
+/// whatever the database features.
+pub(crate) async fn offline_context(config: Config) -> AppContext {
+  AppContext::builder()
+    .config(config)
+    .db(DatabaseConnection::Disconnected)
+    .build()
+    .await
+    .expect("Failed to build the app context")
+}
+
+/// The router of `routes` over an [`offline_context`].
+pub(crate) async fn app(config: Config, routes: AppRoutes) -> Result<Router> {
+  routes.into_router(&offline_context(config).await)
+}
+
+/// What a request got back, the body read as JSON.
+pub(crate) struct Sent {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+  /// `Null` when the body is not JSON.
+  pub body: Value,
+}
+
+impl Sent {
+  /// The status and the `error` code of an
+  /// [`ErrorDetail`](crate::errors::ErrorDetail), empty for other bodies.
+  pub fn error(&self) -> (StatusCode, &str) {
+    (self.status, self.body["error"].as_str().unwrap_or_default())
+  }
+}
+
+/// Sends `request` to `router` and reads the whole response.
+pub(crate) async fn send(router: &Router, request: Request) -> Sent {
+  let response = router.clone().oneshot(request).await.unwrap();
+  let status = response.status();
+  let headers = response.headers().clone();
+  let body = response.into_body().collect().await.unwrap().to_bytes();
+  Sent {
+    status,
+    headers,
+    body: serde_json::from_slice(&body).unwrap_or_default(),
+  }
+}
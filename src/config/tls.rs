@@ -0,0 +1,271 @@
+//! # TLS
+//!
+//! Serves HTTPS with rustls from the PEM files of `server.tls`. The files are
+//! checked every `reload_interval` milliseconds and reloaded when they
+//! change, so a renewed certificate is used by the next connections without a
+//! restart. A file that cannot be loaded is reported and the previous
+//! certificates stay in use.
+//!
+//! With `client_ca` set the server requires mutual TLS: only terminals
+//! presenting a certificate signed by that CA can connect.
+//!
+//! ```toml
+//! [server.tls]
+//! cert = "certs/server.crt"
+//! key = "certs/server.key"
+//! client_ca = "certs/terminals-ca.crt"
+//! ```
+use crate::config::Tls;
+use crate::errors::StartupErrorKind;
+use crate::{Error, Result};
+use axum::serve::Listener;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+
+/// Time a client has to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken connections waiting for the server to pick them up.
+const BACKLOG: usize = 64;
+
+type SharedConfig = Arc<RwLock<Arc<ServerConfig>>>;
+
+/// Builds the rustls configuration from the files of `server.tls`.
+///
+/// # Errors
+///
+/// Returns an [`Error::Startup`] naming the file at fault when a file cannot
+/// be read or holds no usable certificate or key.
+pub fn server_config(tls: &Tls) -> Result<ServerConfig> {
+  let provider = Arc::new(ring::default_provider());
+  let certs = read_certs(&tls.cert, "server.tls.cert")?;
+  let key = read_key(&tls.key, "server.tls.key")?;
+
+  let builder = ServerConfig::builder_with_provider(provider.clone())
+    .with_safe_default_protocol_versions()
+    .map_err(|err| config_error("server.tls", err))?;
+  let builder = match &tls.client_ca {
+    Some(path) => {
+      let mut roots = RootCertStore::empty();
+      for cert in read_certs(path, "server.tls.client_ca")? {
+        roots
+          .add(cert)
+          .map_err(|err| config_error("server.tls.client_ca", err))?;
+      }
+      let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(|err| config_error("server.tls.client_ca", err))?;
+      builder.with_client_cert_verifier(verifier)
+    }
+    None => builder.with_no_client_auth(),
+  };
+
+  let mut config = builder
+    .with_single_cert(certs, key)
+    .map_err(|err| config_error("server.tls.key", err))?;
+  config.alpn_protocols = vec![b"http/1.1".to_vec()];
+  Ok(config)
+}
+
+fn read_certs(path: &Path, setting: &str) -> Result<Vec<CertificateDer<'static>>> {
+  let file = File::open(path).map_err(|err| file_error(setting, path, err))?;
+  let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+    .collect::<io::Result<Vec<_>>>()
+    .map_err(|err| file_error(setting, path, err))?;
+  if certs.is_empty() {
+    return Err(file_error(setting, path, "no certificate found"));
+  }
+  Ok(certs)
+}
+
+fn read_key(path: &Path, setting: &str) -> Result<PrivateKeyDer<'static>> {
+  let file = File::open(path).map_err(|err| file_error(setting, path, err))?;
+  rustls_pemfile::private_key(&mut BufReader::new(file))
+    .map_err(|err| file_error(setting, path, err))?
+    .ok_or_else(|| file_error(setting, path, "no private key found"))
+}
+
+fn file_error(setting: &str, path: &Path, err: impl std::fmt::Display) -> Error {
+  config_error(setting, format!("{}: {err}", path.display()))
+}
+
+fn config_error(setting: &str, err: impl std::fmt::Display) -> Error {
+  Error::startup(
+    StartupErrorKind::Config,
+    setting,
+    Error::Message(err.to_string()),
+  )
+}
+
+/// Accepts TCP connections and hands them to the server once the TLS
+/// handshake succeeded.
+///
+/// Handshakes run in their own tasks, so a slow client does not hold up the
+/// others. Once stopped, see [`TlsListener::stop_token`], the socket is
+/// closed and the handshakes under way are dropped.
+pub struct TlsListener {
+  incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+  local_addr: SocketAddr,
+  stop: CancellationToken,
+}
+
+impl TlsListener {
+  /// Starts accepting on `listener` with the certificates of `tls`, and
+  /// watches them for changes.
+  ///
+  /// # Errors
+  ///
+  /// See [`server_config`].
+  pub fn new(listener: TcpListener, tls: &Tls) -> Result<Self> {
+    let config: SharedConfig = Arc::new(RwLock::new(Arc::new(server_config(tls)?)));
+    let local_addr = listener.local_addr()?;
+    let (sender, incoming) = mpsc::channel(BACKLOG);
+    let stop = CancellationToken::new();
+
+    if tls.reload_interval > 0 {
+      tokio::spawn(watch(tls.clone(), Arc::downgrade(&config)));
+    }
+    tokio::spawn(accept(listener, config, sender, stop.clone()));
+    Ok(Self {
+      incoming,
+      local_addr,
+      stop,
+    })
+  }
+
+  /// Cancelled to stop accepting connections, as when the graceful shutdown
+  /// starts. Dropping the listener stops it too.
+  #[must_use]
+  pub fn stop_token(&self) -> CancellationToken {
+    self.stop.clone()
+  }
+}
+
+impl Drop for TlsListener {
+  fn drop(&mut self) {
+    self.stop.cancel();
+  }
+}
+
+impl std::fmt::Debug for TlsListener {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("TlsListener")
+      .field("local_addr", &self.local_addr)
+      .finish()
+  }
+}
+
+impl Listener for TlsListener {
+  type Io = TlsStream<TcpStream>;
+  type Addr = SocketAddr;
+
+  async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+    match self.incoming.recv().await {
+      Some(connection) => connection,
+      // The accept task only ends once this listener is stopped or dropped
+      None => std::future::pending().await,
+    }
+  }
+
+  fn local_addr(&self) -> io::Result<Self::Addr> {
+    Ok(self.local_addr)
+  }
+}
+
+/// Runs until the [`TlsListener`] is stopped or dropped.
+async fn accept(
+  listener: TcpListener,
+  config: SharedConfig,
+  sender: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+  stop: CancellationToken,
+) {
+  loop {
+    let accepted = tokio::select! {
+      () = stop.cancelled() => return,
+      accepted = listener.accept() => accepted,
+    };
+    let (stream, addr) = match accepted {
+      Ok(accepted) => accepted,
+      Err(err) => {
+        // Usually out of file descriptors, give the server time to close some
+        tracing::warn!(%err, "accept failed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        continue;
+      }
+    };
+
+    let acceptor = TlsAcceptor::from(config.read().expect("tls config lock").clone());
+    let sender = sender.clone();
+    let stop = stop.clone();
+    tokio::spawn(async move {
+      let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+      let handshake = tokio::select! {
+        () = stop.cancelled() => return,
+        handshake = handshake => handshake,
+      };
+      match handshake {
+        Ok(Ok(stream)) => {
+          let _ = sender.send((stream, addr)).await;
+        }
+        Ok(Err(err)) => tracing::debug!(%addr, %err, "TLS handshake failed"),
+        Err(_) => tracing::debug!(%addr, "TLS handshake timed out"),
+      }
+    });
+  }
+}
+
+/// Reloads the configuration when one of the files changes, until the
+/// listener is dropped.
+async fn watch(tls: Tls, config: Weak<RwLock<Arc<ServerConfig>>>) {
+  let mut interval = tokio::time::interval(Duration::from_millis(tls.reload_interval));
+  interval.tick().await;
+  let mut stamps = file_stamps(&tls);
+  loop {
+    interval.tick().await;
+    let Some(config) = config.upgrade() else {
+      return;
+    };
+    let current = file_stamps(&tls);
+    if current == stamps {
+      continue;
+    }
+    // Remembered even on failure, so a half-written renewal is reported once
+    // and picked up when the next file lands
+    stamps = current;
+    match server_config(&tls) {
+      Ok(reloaded) => {
+        *config.write().expect("tls config lock") = Arc::new(reloaded);
+        tracing::info!(cert = %tls.cert.display(), "TLS certificates reloaded");
+      }
+      Err(err) => {
+        tracing::warn!(%err, "TLS certificates changed but cannot be loaded, keeping the previous ones");
+      }
+    }
+  }
+}
+
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn file_stamps(tls: &Tls) -> Vec<FileStamp> {
+  [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()]
+    .into_iter()
+    .flatten()
+    .map(|path| {
+      let metadata = std::fs::metadata(path).ok()?;
+      Some((metadata.modified().ok()?, metadata.len()))
+    })
+    .collect()
+}
@@ -0,0 +1,48 @@
+//! The API documentation, registered by
+//! [`AppRoutes::with_default_routes`](crate::config::routes_config::AppRoutes::with_default_routes):
+//!
+//! - `GET /api/openapi.json` answers the OpenAPI document of every route, see
+//!   [`crate::config::openapi`];
+//! - `GET /api/docs` serves Swagger UI for it, bundled in the binary so it
+//!   also works without internet access.
+//!
+//! Both are left out when `server.docs` is off, as in production.
+use crate::config::app_context::AppContext;
+use crate::config::format;
+use crate::config::openapi::{DOCUMENT_PATH, UI_PATH};
+use crate::config::routes_config::{join_path, Routes};
+use crate::config::routing::get;
+use crate::Result;
+use axum::{Extension, Router};
+use axum_core::response::Response;
+use std::sync::Arc;
+use utoipa::openapi::OpenApi;
+use utoipa_swagger_ui::{Config, SwaggerUi};
+
+pub fn routes() -> Routes {
+  Routes::new()
+    .add(DOCUMENT_PATH, get(openapi))
+    .name("openapi")
+    .summary("OpenAPI document of the API")
+    .tag("docs")
+}
+
+/// The document is built once with the router, see
+/// [`AppRoutes::into_router`](crate::config::routes_config::AppRoutes::into_router).
+pub async fn openapi(Extension(document): Extension<Arc<OpenApi>>) -> Result<Response> {
+  format::json(document.as_ref())
+}
+
+/// The paths [`swagger_ui`] answers `GET` on under `prefix`: the page, the
+/// redirection to it without the trailing slash, and its files.
+pub(crate) fn swagger_ui_paths(prefix: &str) -> [String; 3] {
+  let path = join_path(&[prefix, UI_PATH]);
+  [format!("{path}/{{*rest}}"), format!("{path}/"), path]
+}
+
+/// Swagger UI, reading the document served by [`routes`] under `prefix`.
+pub fn swagger_ui(prefix: &str) -> Router<AppContext> {
+  SwaggerUi::new(join_path(&[prefix, UI_PATH]))
+    .config(Config::from(join_path(&[prefix, DOCUMENT_PATH])))
+    .into()
+}
@@ -0,0 +1,157 @@
+//! Liveness and readiness endpoints, registered by
+//! [`AppRoutes::with_default_routes`](crate::config::routes_config::AppRoutes::with_default_routes).
+//!
+//! - `GET /_health` answers as long as the server runs;
+//! - `GET /_ready` checks the database, the cache and the migrations, and
+//!   answers `503 Service Unavailable` when one of them fails. The reasons
+//!   are logged, callers only get the status of each check.
+//!
+//! Neither counts against `server.rate_limit`.
+use crate::config::app_context::AppContext;
+use crate::config::format;
+use crate::config::routes_config::Routes;
+use crate::config::routing::get;
+use crate::Result;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum_core::response::Response;
+use migration::{Migrator, MigratorTrait};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Distinguishes the probe keys of concurrent readiness checks.
+static PROBE: AtomicU64 = AtomicU64::new(0);
+
+pub fn routes() -> Routes {
+  Routes::new()
+    .add("/_health", get(health))
+    .name("health")
+    .summary("Liveness probe")
+    .tag("health")
+    .rate_limit_exempt()
+    .add("/_ready", get(ready))
+    .name("ready")
+    .summary("Readiness probe: database, cache and migrations")
+    .tag("health")
+    .rate_limit_exempt()
+}
+
+#[derive(Serialize)]
+pub struct Health {
+  pub ok: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+  Ok,
+  Error,
+  /// Not checked, e.g. the `Null` cache.
+  Disabled,
+}
+
+/// The result of one readiness check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+  pub status: CheckStatus,
+  pub latency_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Checks {
+  pub database: Check,
+  pub cache: Check,
+  pub migrations: Check,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+  pub ok: bool,
+  pub checks: Checks,
+}
+
+/// Liveness: the process is up and serving requests.
+pub async fn health() -> Result<Response> {
+  format::json(Health { ok: true })
+}
+
+/// Readiness: the dependencies answer and the schema is up to date.
+pub async fn ready(State(ctx): State<AppContext>) -> Result<Response> {
+  let checks = Checks {
+    database: timed("database", async {
+      ctx.db.ping().await.map_err(|err| err.to_string())
+    })
+    .await,
+    cache: check_cache(&ctx).await,
+    migrations: timed("migrations", check_migrations(&ctx)).await,
+  };
+  let ok = [&checks.database, &checks.cache, &checks.migrations]
+    .iter()
+    .all(|check| check.status != CheckStatus::Error);
+
+  let mut response = format::json(Readiness { ok, checks })?;
+  if !ok {
+    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+  }
+  Ok(response)
+}
+
+/// Runs `check`, logging why it failed rather than telling the caller.
+async fn timed(name: &str, check: impl Future<Output = std::result::Result<(), String>>) -> Check {
+  let start = Instant::now();
+  let result = check.await;
+  let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+  let status = match result {
+    Ok(()) => CheckStatus::Ok,
+    Err(reason) => {
+      tracing::warn!(check = name, reason, "readiness check failed");
+      CheckStatus::Error
+    }
+  };
+  Check { status, latency_ms }
+}
+
+/// Writes, reads back and removes a probe key.
+async fn check_cache(ctx: &AppContext) -> Check {
+  if ctx.cache.driver.is_null() {
+    return Check {
+      status: CheckStatus::Disabled,
+      latency_ms: 0.0,
+    };
+  }
+  timed("cache", async {
+    let probe = PROBE.fetch_add(1, Ordering::Relaxed).to_string();
+    let key = format!("_ready:{}:{probe}", std::process::id());
+    let cache = &ctx.cache;
+    cache
+      .insert_with_expiry(&key, &probe, Duration::from_secs(60))
+      .await
+      .map_err(|err| format!("write failed: {err}"))?;
+    let read = cache
+      .get(&key)
+      .await
+      .map_err(|err| format!("read failed: {err}"))?;
+    cache
+      .remove(&key)
+      .await
+      .map_err(|err| format!("remove failed: {err}"))?;
+    match read {
+      Some(value) if value == probe => Ok(()),
+      _ => Err("the probe key was not read back".to_string()),
+    }
+  })
+  .await
+}
+
+async fn check_migrations(ctx: &AppContext) -> std::result::Result<(), String> {
+  let pending = Migrator::get_pending_migrations(&ctx.db)
+    .await
+    .map_err(|err| err.to_string())?;
+  if pending.is_empty() {
+    return Ok(());
+  }
+  let names: Vec<_> = pending.iter().map(|m| m.name().to_string()).collect();
+  Err(format!("{} pending: {}", names.len(), names.join(", ")))
+}
@@ -1,3 +1,6 @@
+pub mod docs;
+pub mod health;
+
 // export all public functions from tasks_controller
 pub mod tasks_controller;
 
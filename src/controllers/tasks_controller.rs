@@ -1,25 +1,48 @@
 use crate::config::app_context::AppContext;
 use crate::config::format;
 use crate::config::routes_config::Routes;
+use crate::config::routing::{delete, get, patch, post};
 use crate::entity::prelude::Task;
 use crate::entity::task;
 use crate::Result;
 use axum::extract::State;
-use axum::routing::get;
 use axum::{extract::Path, http::StatusCode, response::IntoResponse, Json};
 use axum_core::response::Response;
 use sea_orm::{ActiveModelTrait, DeleteResult, EntityTrait, ModelTrait, Set};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use utoipa::ToSchema;
 
 pub fn routes() -> Routes {
   Routes::new()
     .prefix("/api/tasks")
-    .add("/", get(get_tasks).post(create_task))
-    .add(
-      "/{id}",
-      get(get_task).patch(update_task).delete(delete_taks),
-    )
+    .add("/", get(get_tasks))
+    .name("list_tasks")
+    .summary("List the tasks")
+    .tag("tasks")
+    .response_list::<task::Model>(StatusCode::OK)
+    .add("/", post(create_task))
+    .name("create_task")
+    .summary("Create a task")
+    .tag("tasks")
+    .request::<CreateTask>()
+    .response::<CreatedTask>(StatusCode::CREATED)
+    .add("/{id}", get(get_task))
+    .name("get_task")
+    .summary("Get a task")
+    .tag("tasks")
+    .response_list::<task::Model>(StatusCode::OK)
+    .add("/{id}", patch(update_task))
+    .name("update_task")
+    .summary("Update the title and description of a task")
+    .tag("tasks")
+    .request::<UpdateTask>()
+    .response_status(StatusCode::OK)
+    .add("/{id}", delete(delete_taks))
+    .name("delete_task")
+    .summary("Delete a task")
+    .tag("tasks")
+    .response_status(StatusCode::OK)
 }
 
 pub async fn get_tasks(State(ctx): State<AppContext>) -> Result<Response> {
@@ -42,10 +65,10 @@ pub async fn get_tasks(State(ctx): State<AppContext>) -> Result<Response> {
   format::json(tasks)
 }
 
-pub async fn get_task(State(ctx): State<AppContext>, Path(id): Path<u16>) -> impl IntoResponse {
-  // let db = db_connection().await.unwrap();
-
-  let task = Task::find_by_id(id).one(&ctx.db).await.unwrap();
+/// Answers a list holding the task, empty when there is none: the shape the
+/// deployed tills read.
+pub async fn get_task(State(ctx): State<AppContext>, Path(id): Path<u16>) -> Result<Response> {
+  let task = Task::find_by_id(id).one(&ctx.db).await?;
 
   let task = task
     .into_iter()
@@ -58,10 +81,10 @@ pub async fn get_task(State(ctx): State<AppContext>, Path(id): Path<u16>) -> imp
     })
     .collect::<Vec<serde_json::Value>>();
 
-  Json(serde_json::json!(task))
+  format::json(task)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateTask {
   title: String,
   description: String,
@@ -74,8 +97,8 @@ pub async fn create_task(
   // let db = db_connection().await.unwrap();
 
   let task = task::ActiveModel {
-    title: Set(String::from(body.title)),
-    description: Set(String::from(body.description)),
+    title: Set(body.title),
+    description: Set(body.description),
     ..Default::default() // all other attributes are `NotSet`
   };
 
@@ -83,14 +106,20 @@ pub async fn create_task(
 
   (
     StatusCode::CREATED,
-    Json(serde_json::json!({
-      "id": task.id,
-      "title": task.title,
-    })),
+    Json(CreatedTask {
+      id: task.id,
+      title: task.title,
+    }),
   )
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedTask {
+  id: i32,
+  title: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateTask {
   title: String,
   description: String,
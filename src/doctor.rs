@@ -0,0 +1,138 @@
+//! # Doctor
+//!
+//! Checks that the environment of the application is usable: the expected
+//! variables and config file are present, the database answers, and no
+//! migration is pending.
+use crate::config::{db, Config, MigrationPolicy, DEFAULT_FOLDER};
+use colored::Colorize;
+use migration::{Migrator, MigratorTrait};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+  Ok,
+  NotOk,
+}
+
+/// The outcome of a single check.
+#[derive(Debug)]
+pub struct Check {
+  pub status: CheckStatus,
+  pub message: String,
+  pub description: Option<String>,
+}
+
+impl Check {
+  fn ok(message: impl Into<String>) -> Self {
+    Self {
+      status: CheckStatus::Ok,
+      message: message.into(),
+      description: None,
+    }
+  }
+
+  fn not_ok(message: impl Into<String>, description: impl Into<String>) -> Self {
+    Self {
+      status: CheckStatus::NotOk,
+      message: message.into(),
+      description: Some(description.into()),
+    }
+  }
+
+  #[must_use]
+  pub fn valid(&self) -> bool {
+    self.status == CheckStatus::Ok
+  }
+}
+
+impl fmt::Display for Check {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let icon = match self.status {
+      CheckStatus::Ok => "✅",
+      CheckStatus::NotOk => "❌",
+    };
+    write!(f, "{icon} {}", self.message)?;
+    if let Some(description) = &self.description {
+      write!(f, "\n    {}", description.dimmed())?;
+    }
+    Ok(())
+  }
+}
+
+/// Runs every check against the given configuration.
+pub async fn run_all(config: &Config) -> Vec<Check> {
+  let mut checks = vec![check_config_file(config), check_database_url(config)];
+  checks.extend(check_database(config).await);
+  checks
+}
+
+fn check_config_file(config: &Config) -> Check {
+  let env = config.environment.to_string();
+  let folder = Path::new(DEFAULT_FOLDER);
+  let found = ["toml", "yaml", "yml"]
+    .iter()
+    .map(|ext| folder.join(format!("{env}.{ext}")))
+    .find(|path| path.exists());
+  match found {
+    Some(path) => Check::ok(format!("config: {}", path.display())),
+    None => Check::not_ok(
+      format!("config: no `{env}` profile in `{DEFAULT_FOLDER}/`"),
+      "running on defaults and APP__SECTION__KEY variables only",
+    ),
+  }
+}
+
+fn check_database_url(config: &Config) -> Check {
+  if config.database.uri.is_some() {
+    Check::ok("database url: set by `database.uri`")
+  } else if std::env::var("DATABASE_URL").is_ok() {
+    Check::ok("database url: set by DATABASE_URL")
+  } else {
+    Check::not_ok(
+      "database url: missing",
+      "set `database.uri` in the config file, or the DATABASE_URL variable",
+    )
+  }
+}
+
+async fn check_database(config: &Config) -> Vec<Check> {
+  let mut config = config.clone();
+  config.database.migrations = MigrationPolicy::Skip;
+
+  let db = match db::connect(&config).await {
+    Ok(db) => db,
+    Err(err) => return vec![Check::not_ok("database: unreachable", err.to_string())],
+  };
+
+  let mut checks = match db.ping().await {
+    Ok(()) => vec![Check::ok("database: reachable")],
+    Err(err) => vec![Check::not_ok("database: ping failed", err.to_string())],
+  };
+  checks.push(match Migrator::get_pending_migrations(&db).await {
+    Ok(pending) if pending.is_empty() => Check::ok("migrations: up to date"),
+    Ok(pending) => Check::not_ok(
+      format!("migrations: {} pending", pending.len()),
+      pending
+        .iter()
+        .map(|m| m.name().to_string())
+        .collect::<Vec<_>>()
+        .join(", "),
+    ),
+    Err(err) => Check::not_ok("migrations: cannot read status", err.to_string()),
+  });
+  let _ = db.close().await;
+
+  match db::connect_replica(&config).await {
+    Ok(None) => {}
+    Ok(Some(replica)) => {
+      checks.push(match replica.ping().await {
+        Ok(()) => Check::ok("replica: reachable"),
+        Err(err) => Check::not_ok("replica: ping failed", err.to_string()),
+      });
+      let _ = replica.close().await;
+    }
+    Err(err) => checks.push(Check::not_ok("replica: unreachable", err.to_string())),
+  }
+  checks
+}
@@ -2,9 +2,11 @@
 
 use crate::entity::task;
 use sea_orm::entity::prelude::*;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, ToSchema)]
 #[sea_orm(table_name = "task")]
+#[schema(as = Task)]
 pub struct Model {
   #[sea_orm(primary_key)]
   pub id: i32,
@@ -1,18 +1,21 @@
 //! # Application Error Handling
 
+use crate::config::request_id::RequestId;
 use crate::Result;
 use axum::extract::FromRequest;
 use axum::{
   extract::rejection::JsonRejection,
   http::{
-    header::{InvalidHeaderName, InvalidHeaderValue},
+    header::{InvalidHeaderName, InvalidHeaderValue, ALLOW, RETRY_AFTER},
     method::InvalidMethod,
-    StatusCode,
+    HeaderValue, Method, StatusCode,
   },
 };
 use axum_core::__private::tracing;
 use axum_core::response::{IntoResponse, Response};
 use serde::Serialize;
+use std::time::Duration;
+use utoipa::ToSchema;
 
 /*
 backtrace principles:
@@ -40,6 +43,24 @@ pub enum Error {
   #[error("{0}")]
   Message(String),
 
+  #[error("{kind} (`{setting}`): {source}")]
+  Startup {
+    kind: StartupErrorKind,
+    setting: String,
+    #[source]
+    source: Box<Self>,
+  },
+
+  /// Two handlers registered in
+  /// [`AppRoutes`](crate::config::routes_config::AppRoutes) answer the same
+  /// requests.
+  #[error("route `{first}` conflicts with `{second}`: {reason}")]
+  RouteConflict {
+    first: String,
+    second: String,
+    reason: String,
+  },
+
   #[error(
     "error while running worker: no queue provider populated in context. Did you configure \
          BackgroundQueue and connection details in `queue` in your config file?"
@@ -62,11 +83,14 @@ pub enum Error {
   #[error(transparent)]
   JsonRejection(#[from] JsonRejection),
 
-  // #[error("cannot parse `{1}`: {0}")]
-  // YAMLFile(#[source] serde_yaml::Error, String),
+  #[error("cannot parse `{1}`: {0}")]
+  YAMLFile(#[source] serde_yaml::Error, String),
 
-  // #[error(transparent)]
-  // YAML(#[from] serde_yaml::Error),
+  #[error(transparent)]
+  YAML(#[from] serde_yaml::Error),
+
+  #[error("cannot parse `{1}`: {0}")]
+  TOMLFile(#[source] toml::de::Error, String),
   #[error(transparent)]
   EnvVar(#[from] std::env::VarError),
 
@@ -90,15 +114,40 @@ pub enum Error {
   #[error("not found")]
   NotFound,
 
+  /// The path exists, but does not answer the method: the methods it
+  /// answers are sent in the `Allow` header.
+  #[error("method not allowed")]
+  MethodNotAllowed(Vec<Method>),
+
   #[error("{0}")]
   BadRequest(String),
 
+  /// The request was not answered within `server.request_timeout`.
+  #[error("request timed out")]
+  Timeout,
+
+  /// The request body stalled for `server.body_timeout`.
+  #[error("request body timed out")]
+  BodyTimeout,
+
+  /// The request body is over `server.body_limit`.
+  #[error("payload too large")]
+  PayloadTooLarge,
+
+  /// A rate limit was hit, the client may retry after the duration, sent in
+  /// the `Retry-After` header.
+  #[error("too many requests, retry after {0:?}")]
+  TooManyRequests(Duration),
+
   #[error("")]
   CustomError(StatusCode, ErrorDetail),
 
   #[error("internal server error")]
   InternalServerError,
 
+  #[error("extension `{0}` is not registered in the app context")]
+  ExtensionMissing(&'static str),
+
   #[error(transparent)]
   InvalidHeaderValue(#[from] InvalidHeaderValue),
 
@@ -149,6 +198,27 @@ impl Error {
   pub fn string(s: &str) -> Self {
     Self::Message(s.to_string())
   }
+  /// Wraps `err` as a startup failure of the given class, naming the
+  /// setting that caused it.
+  #[must_use]
+  pub fn startup(kind: StartupErrorKind, setting: impl Into<String>, err: impl Into<Self>) -> Self {
+    Self::Startup {
+      kind,
+      setting: setting.into(),
+      source: Box::new(err.into()),
+    }
+  }
+
+  /// The process exit code for this error, see [`StartupErrorKind`].
+  #[must_use]
+  pub fn exit_code(&self) -> u8 {
+    match self {
+      Self::Startup { kind, .. } => kind.exit_code(),
+      Self::WithBacktrace { inner, .. } => inner.exit_code(),
+      _ => 1,
+    }
+  }
+
   #[must_use]
   pub fn bt(self) -> Self {
     let backtrace = std::backtrace::Backtrace::capture();
@@ -178,8 +248,7 @@ impl Error {
 /// ```rust
 ///
 /// use axum_core::response::Response;
-/// use tracing_subscriber::fmt::format;///
-///
+/// use pos_rust_local_backend::config::format;
 /// use pos_rust_local_backend::errors::unauthorized;
 ///
 /// async fn login() -> pos_rust_local_backend::Result<Response> {
@@ -187,9 +256,9 @@ impl Error {
 ///     if !valid {
 ///         return unauthorized("unauthorized access");
 ///     }
-///     format::json()
+///     format::empty_json()
 /// }
-/// ````
+/// ```
 pub fn unauthorized<T: Into<String>, U>(msg: T) -> Result<U> {
   Err(Error::Unauthorized(msg.into()))
 }
@@ -211,13 +280,16 @@ pub fn bad_request<T: Into<String>, U>(msg: T) -> Result<U> {
 pub fn not_found<T>() -> Result<T> {
   Err(Error::NotFound)
 }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 /// Structure representing details about an error.
 pub struct ErrorDetail {
   #[serde(skip_serializing_if = "Option::is_none")]
   pub error: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub description: Option<String>,
+  /// The ID of the request that failed, see [`RequestId`].
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
 }
 
 impl ErrorDetail {
@@ -227,6 +299,7 @@ impl ErrorDetail {
     Self {
       error: Some(error.into()),
       description: Some(description.into()),
+      request_id: RequestId::current().map(|id| id.to_string()),
     }
   }
 
@@ -236,6 +309,7 @@ impl ErrorDetail {
     Self {
       error: Some(error.into()),
       description: None,
+      request_id: RequestId::current().map(|id| id.to_string()),
     }
   }
 }
@@ -273,12 +347,39 @@ impl IntoResponse for Error {
       }
     }
 
+    let allow = match &self {
+      Self::MethodNotAllowed(methods) => Some(
+        methods
+          .iter()
+          .map(Method::as_str)
+          .collect::<Vec<_>>()
+          .join(", "),
+      ),
+      _ => None,
+    };
+    // Whole seconds, rounded up so that the retry is not limited again
+    let retry_after = match &self {
+      Self::TooManyRequests(wait) => {
+        Some((wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1))
+      }
+      _ => None,
+    };
     let public_facing_error = match self {
       Self::NotFound => (
         StatusCode::NOT_FOUND,
         ErrorDetail::new("not_found", "Resource was not found"),
       ),
-      Self::InternalServerError => (
+      Self::MethodNotAllowed(_) => (
+        StatusCode::METHOD_NOT_ALLOWED,
+        ErrorDetail::new(
+          "method_not_allowed".to_string(),
+          format!(
+            "Method is not allowed on this resource, use one of: {}",
+            allow.as_deref().unwrap_or_default()
+          ),
+        ),
+      ),
+      Self::InternalServerError | Self::ExtensionMissing(_) => (
         StatusCode::INTERNAL_SERVER_ERROR,
         ErrorDetail::new("internal_server_error", "Internal Server Error"),
       ),
@@ -292,6 +393,38 @@ impl IntoResponse for Error {
           ),
         )
       }
+      Self::TooManyRequests(_) => (
+        StatusCode::TOO_MANY_REQUESTS,
+        ErrorDetail::new(
+          "too_many_requests".to_string(),
+          format!(
+            "Too many requests, retry in {} seconds",
+            retry_after.unwrap_or_default()
+          ),
+        ),
+      ),
+      Self::Timeout => (
+        StatusCode::GATEWAY_TIMEOUT,
+        ErrorDetail::new("timeout", "The request took too long to answer"),
+      ),
+      Self::BodyTimeout => (
+        StatusCode::REQUEST_TIMEOUT,
+        ErrorDetail::new(
+          "request_timeout",
+          "The request body was not received in time",
+        ),
+      ),
+      Self::PayloadTooLarge => (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorDetail::new("payload_too_large", "The request body is too large"),
+      ),
+      Self::JsonRejection(rejection) => match body_rejection(&rejection) {
+        Some(err) => return err.into_response(),
+        None => (
+          StatusCode::BAD_REQUEST,
+          ErrorDetail::with_reason("Bad Request"),
+        ),
+      },
       Self::CustomError(status_code, data) => (status_code, data),
       // Self::WithBacktrace { inner, backtrace } => {
       //     println!("\n{}", inner.to_string().red().underline());
@@ -307,8 +440,30 @@ impl IntoResponse for Error {
       ),
     };
 
-    (public_facing_error.0, Json(public_facing_error.1)).into_response()
+    let mut response = (public_facing_error.0, Json(public_facing_error.1)).into_response();
+    if let Some(allow) = allow.and_then(|allow| HeaderValue::from_str(&allow).ok()) {
+      response.headers_mut().insert(ALLOW, allow);
+    }
+    if let Some(retry_after) = retry_after {
+      response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+    response
+  }
+}
+
+/// The error of a JSON body that could not be read because it is too large
+/// or too slow, see [`crate::config::middleware`], `None` when malformed.
+fn body_rejection(rejection: &JsonRejection) -> Option<Error> {
+  let mut source: Option<&(dyn std::error::Error + 'static)> = Some(rejection);
+  while let Some(err) = source {
+    if err.is::<tower_http::timeout::TimeoutError>() {
+      return Some(Error::BodyTimeout);
+    }
+    source = err.source();
   }
+  (rejection.status() == StatusCode::PAYLOAD_TOO_LARGE).then_some(Error::PayloadTooLarge)
 }
 
 use sea_orm::sqlx;
@@ -344,3 +499,53 @@ pub enum ModelError {
 
 #[allow(clippy::module_name_repetitions)]
 pub type ModelResult<T, E = ModelError> = std::result::Result<T, E>;
+
+/// Class of a failure that prevents the server from starting.
+///
+/// Each class exits with its own code, following `sysexits.h`, so a
+/// supervisor can tell a bad configuration from a busy database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum StartupErrorKind {
+  /// A setting is missing or invalid. Exits with 78 (`EX_CONFIG`).
+  Config,
+  /// The database cannot be reached. Exits with 69 (`EX_UNAVAILABLE`).
+  DatabaseUnavailable,
+  /// The database file is locked by another process. Exits with 75
+  /// (`EX_TEMPFAIL`), retrying later may succeed.
+  DatabaseLocked,
+  /// Migrations failed or are pending. Exits with 65 (`EX_DATAERR`).
+  Migration,
+  /// The listener cannot be bound. Exits with 74 (`EX_IOERR`).
+  Listener,
+  /// The server stopped with an error. Exits with 70 (`EX_SOFTWARE`).
+  Server,
+}
+
+impl StartupErrorKind {
+  #[must_use]
+  pub fn exit_code(self) -> u8 {
+    match self {
+      Self::Config => 78,
+      Self::DatabaseUnavailable => 69,
+      Self::DatabaseLocked => 75,
+      Self::Migration => 65,
+      Self::Listener => 74,
+      Self::Server => 70,
+    }
+  }
+}
+
+impl std::fmt::Display for StartupErrorKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let description = match self {
+      Self::Config => "invalid configuration",
+      Self::DatabaseUnavailable => "database unavailable",
+      Self::DatabaseLocked => "database is locked by another process",
+      Self::Migration => "database migration failed",
+      Self::Listener => "cannot bind the listener",
+      Self::Server => "server error",
+    };
+    f.write_str(description)
+  }
+}
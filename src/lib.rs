@@ -2,8 +2,16 @@
 
 pub use self::errors::Error;
 
+#[cfg(not(any(feature = "db-sqlite", feature = "db-postgres", feature = "db-mysql")))]
+compile_error!("enable at least one database backend: `db-sqlite`, `db-postgres` or `db-mysql`");
+
+pub mod app;
+pub mod cli;
 pub mod config;
 pub mod controllers;
+pub mod doctor;
+pub mod logger;
+pub mod shutdown;
 
 pub mod cache;
 pub mod entity;
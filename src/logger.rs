@@ -0,0 +1,43 @@
+//! # Logging
+//!
+//! Installs the `tracing` subscriber described by the `logger` section of the
+//! configuration.
+use crate::config::{Config, LogFormat};
+use tracing_subscriber::filter::LevelFilter;
+
+/// Installs the tracing subscriber and, outside production, prints the
+/// startup banner.
+///
+/// Does nothing when a global subscriber is already installed, so it can be
+/// called from tests.
+pub fn init(config: &Config) {
+  if config.logger.enable {
+    let builder = tracing_subscriber::fmt().with_max_level(LevelFilter::from(config.logger.level));
+    // `try_init` fails when a subscriber is already set, which is fine
+    let _ = match config.logger.format {
+      LogFormat::Compact => builder.compact().try_init(),
+      LogFormat::Pretty => builder.pretty().try_init(),
+      LogFormat::Json => builder.json().try_init(),
+    };
+  }
+  if config.environment.is_debug() {
+    println!(
+      "{} ({}) {}",
+      env!("CARGO_PKG_VERSION"),
+      option_env!("BUILD_SHA")
+        .or(option_env!("GITHUB_SHA"))
+        .unwrap_or("dev"),
+      env!("CARGO_CRATE_NAME")
+    );
+    println!("Logging enabled {}", config.logger.enable);
+    println!("Environment: {}", config.environment);
+    println!(
+      "Compilation mode: {}",
+      if cfg!(debug_assertions) {
+        "Debug"
+      } else {
+        "Release"
+      }
+    );
+  }
+}
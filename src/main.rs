@@ -1,106 +1,73 @@
+use async_trait::async_trait;
+use pos_rust_local_backend::app::Hooks;
 use pos_rust_local_backend::config::app_context::AppContext;
-use pos_rust_local_backend::config::db::db_connection;
+use pos_rust_local_backend::config::listener;
 use pos_rust_local_backend::config::routes_config::AppRoutes;
-use pos_rust_local_backend::controllers;
-use std::net::SocketAddr;
-use axum_core::__private::tracing;
-use sea_orm::DatabaseConnection;
-use tokio::signal;
+use pos_rust_local_backend::config::static_assets::StaticAssets;
+use pos_rust_local_backend::entity::{prelude::Task, task};
+use pos_rust_local_backend::{cli, controllers, Result};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, Set};
+use std::process::ExitCode;
 
-pub fn routes(_ctx: &AppContext) -> AppRoutes {
-  AppRoutes::with_default_routes().add_route(controllers::tasks_controller::routes())
-}
-
-#[tokio::main]
-async fn main() {
-  // Initialize the database connection
-  let ctx = db_connection()
-    .await
-    .expect("Failed to connect to the database");
-
-  // Create a new router with the shared state
-  let app = routes(&ctx).into_router(&ctx);
-
-  // Initialize tracing
-  setup_logging(&ctx.config.debug_mode);
-
-  // Print server information
-  // println!("Server running on port {}", 3000);
-  
-
-  // Create a new listener
-  let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-  let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-  println!("Server running on {:?}", addr);
+struct App;
 
-  // Start the server with graceful shutdown
-  axum::serve(listener, app)
-    .with_graceful_shutdown(shutdown_signal(ctx.db.clone()))
-    .await
-    .unwrap();
-}
-
-/// Handles graceful shutdown by listening for SIGINT (Ctrl+C) and SIGTERM signals.
-async fn shutdown_signal(db: DatabaseConnection) {
-  let ctrl_c = async {
-    signal::ctrl_c()
-      .await
-      .expect("Failed to install Ctrl+C handler");
-  };
-
-  #[cfg(unix)]
-  let terminate = async {
-    signal::unix::signal(signal::unix::SignalKind::terminate())
-      .expect("Failed to install SIGTERM handler")
-      .recv()
-      .await;
-  };
+/// The frontend built into the binary, see `StaticAssets::embedded`.
+#[cfg(feature = "embed-assets")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "frontend/dist"]
+#[allow_missing = true]
+struct Frontend;
 
-  #[cfg(not(unix))]
-  let terminate = std::future::pending::<()>();
-
-  tokio::select! {
-      _ = ctrl_c => {},
-      _ = terminate => {},
+#[async_trait]
+impl Hooks for App {
+  fn routes(ctx: &AppContext) -> AppRoutes {
+    let routes =
+      AppRoutes::with_default_routes().add_route(controllers::tasks_controller::routes());
+    match &ctx.config.server.assets {
+      #[cfg(feature = "embed-assets")]
+      Some(config) => routes.assets(StaticAssets::embedded::<Frontend>().options(config)),
+      #[cfg(not(feature = "embed-assets"))]
+      Some(config) => routes.assets(StaticAssets::from_config(config)),
+      None => routes,
+    }
   }
 
-  if let Err(e) = db.close().await {
-    tracing::error!("Error during database closure: {}", e);
-  } else {
-    tracing::info!("Database connection closed successfully");
+  async fn seed(ctx: &AppContext) -> Result<()> {
+    let tasks = [
+      ("Open the register", "Count the float and log in"),
+      (
+        "Close the register",
+        "Print the Z report and count the drawer",
+      ),
+    ];
+    for (title, description) in tasks {
+      let exists = Task::find()
+        .filter(task::Column::Title.eq(title))
+        .count(&ctx.db)
+        .await?
+        > 0;
+      if !exists {
+        task::ActiveModel {
+          title: Set(title.to_string()),
+          description: Set(description.to_string()),
+          ..Default::default()
+        }
+        .insert(&ctx.db)
+        .await?;
+      }
+    }
+    Ok(())
   }
-
-  tracing::info!("Shutting down gracefully...");
-  
-  println!("Shutting down gracefully...");
 }
 
-fn setup_logging(debug_mode: &bool) {
-  if *debug_mode {
-    // Enable logging in debug mode
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .with_test_writer()
-        .init();
-    println!(
-      "{} ({}) {}",
-      env!("CARGO_PKG_VERSION"),
-      option_env!("BUILD_SHA")
-          .or(option_env!("GITHUB_SHA"))
-          .unwrap_or("dev"),
-      env!("CARGO_CRATE_NAME")
-    );
-    println!("Logging enabled {}",debug_mode);
-    println!(
-      "Compilation mode: {}",
-      if *debug_mode { "Debug" } else { "Release" }
-    );
-  } else {
-    // Disable logging in release mode
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::ERROR) // Minimal logging
-        .without_time() // Optional: omit timestamps in release
-        .init();
+fn main() -> ExitCode {
+  // Before the runtime starts the threads that may read the environment
+  listener::take_systemd_env();
+  match tokio::runtime::Runtime::new() {
+    Ok(runtime) => runtime.block_on(cli::main::<App>()),
+    Err(err) => {
+      eprintln!("error: could not start the tokio runtime: {err}");
+      ExitCode::FAILURE
+    }
   }
 }
-
@@ -0,0 +1,390 @@
+//! # Shutdown
+//!
+//! Coordinates the end of the server once `SIGINT` or `SIGTERM` arrives:
+//!
+//! 1. the listener stops accepting connections;
+//! 2. in-flight requests get `server.drain_timeout` milliseconds to finish,
+//!    the body of their response included. Those still running are then
+//!    aborted: a handler is dropped and answers `503`, a body being sent is
+//!    cut;
+//! 3. [`Hooks::on_shutdown`](crate::app::Hooks::on_shutdown) runs, then the
+//!    hooks registered on [`Shutdown`], the last registered first. The
+//!    database is registered right after it connects, so it closes last.
+//!
+//! A second signal at any point exits the process immediately.
+//!
+//! # Example
+//!
+//! ```rust
+//! use pos_rust_local_backend::config::app_context::AppContext;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> pos_rust_local_backend::Result<()> {
+//! let ctx = AppContext::builder().build().await?;
+//! ctx.shutdown.register("receipt printer", |_ctx| async {
+//!     // flush the pending receipts
+//!     Ok(())
+//! });
+//! # Ok(())
+//! # }
+//! ```
+use crate::config::app_context::AppContext;
+use crate::errors::ErrorDetail;
+use crate::{Error, Result};
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http_body::{Frame, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::signal;
+use tokio::sync::{watch, Notify};
+
+/// Exit code when a second signal interrupts the shutdown, as for a process
+/// killed by `SIGINT`.
+pub const FORCED_EXIT_CODE: i32 = 130;
+
+type HookFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+type HookFn = Box<dyn FnOnce(AppContext) -> HookFuture + Send>;
+
+/// Shutdown hooks and in-flight request count, shared through the
+/// [`AppContext`].
+///
+/// Cloning is cheap, every clone shares the same state.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+  inner: Arc<Inner>,
+}
+
+struct Inner {
+  hooks: Mutex<Vec<(String, HookFn)>>,
+  in_flight: AtomicUsize,
+  /// Notified when the last request in flight ends.
+  idle: Notify,
+  /// Set once the requests in flight are aborted.
+  aborted: watch::Sender<bool>,
+}
+
+impl Default for Inner {
+  fn default() -> Self {
+    Self {
+      hooks: Mutex::default(),
+      in_flight: AtomicUsize::new(0),
+      idle: Notify::new(),
+      aborted: watch::Sender::new(false),
+    }
+  }
+}
+
+impl Shutdown {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Registers a hook to run on shutdown, before the hooks registered
+  /// earlier. A failing hook is logged and does not stop the others.
+  pub fn register<F, Fut>(&self, name: impl Into<String>, hook: F)
+  where
+    F: FnOnce(AppContext) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+  {
+    let hook: HookFn = Box::new(move |ctx| Box::pin(hook(ctx)));
+    self
+      .inner
+      .hooks
+      .lock()
+      .expect("shutdown hooks lock")
+      .push((name.into(), hook));
+  }
+
+  /// Names of the registered hooks, in the order they will run.
+  #[must_use]
+  pub fn hooks(&self) -> Vec<String> {
+    let hooks = self.inner.hooks.lock().expect("shutdown hooks lock");
+    hooks.iter().rev().map(|(name, _)| name.clone()).collect()
+  }
+
+  /// Runs the registered hooks, the last registered first, and empties the
+  /// list.
+  pub async fn run_hooks(&self, ctx: &AppContext) {
+    let hooks = std::mem::take(&mut *self.inner.hooks.lock().expect("shutdown hooks lock"));
+    for (name, hook) in hooks.into_iter().rev() {
+      tracing::debug!(hook = %name, "running shutdown hook");
+      if let Err(err) = hook(ctx.clone()).await {
+        tracing::error!(hook = %name, %err, "shutdown hook failed");
+      }
+    }
+  }
+
+  /// Number of requests being handled.
+  #[must_use]
+  pub fn in_flight(&self) -> usize {
+    self.inner.in_flight.load(Ordering::SeqCst)
+  }
+
+  /// Resolves once no request is in flight.
+  pub async fn idle(&self) {
+    loop {
+      // Created before the check, so that the last request ending in
+      // between still wakes it
+      let idle = self.inner.idle.notified();
+      if self.in_flight() == 0 {
+        return;
+      }
+      idle.await;
+    }
+  }
+
+  /// Aborts the requests in flight and those to come: handlers are dropped
+  /// and answer `503`, response bodies end with an error.
+  pub fn abort_in_flight(&self) {
+    self.inner.aborted.send_replace(true);
+  }
+
+  /// Resolves once [`Self::abort_in_flight`] is called.
+  fn aborted(&self) -> impl Future<Output = ()> + Send + 'static {
+    let mut aborted = self.inner.aborted.subscribe();
+    async move {
+      // The sender lives as long as `self`, held by the request
+      if aborted.wait_for(|aborted| *aborted).await.is_err() {
+        std::future::pending::<()>().await;
+      }
+    }
+  }
+
+  fn track(&self) -> InFlight {
+    self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+    InFlight(self.clone())
+  }
+}
+
+impl std::fmt::Debug for Shutdown {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Shutdown")
+      .field("hooks", &self.hooks())
+      .field("in_flight", &self.in_flight())
+      .finish()
+  }
+}
+
+/// Counts a request as in flight until dropped, even if the handler panics
+/// or the client goes away.
+struct InFlight(Shutdown);
+
+impl Drop for InFlight {
+  fn drop(&mut self) {
+    if self.0.inner.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+      self.0.inner.idle.notify_waiters();
+    }
+  }
+}
+
+/// Middleware counting the requests in flight until their response body is
+/// sent, see [`Shutdown::in_flight`], and aborting them with
+/// [`Shutdown::abort_in_flight`].
+pub async fn track_in_flight(
+  State(shutdown): State<Shutdown>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let in_flight = shutdown.track();
+  let response = tokio::select! {
+    response = next.run(request) => response,
+    () = shutdown.aborted() => return aborted().into_response(),
+  };
+  response.map(|body| {
+    Body::new(InFlightBody {
+      body,
+      aborted: Some(Box::pin(shutdown.aborted())),
+      _in_flight: in_flight,
+    })
+  })
+}
+
+fn aborted() -> Error {
+  Error::CustomError(
+    StatusCode::SERVICE_UNAVAILABLE,
+    ErrorDetail::new("shutting_down", "The server is shutting down"),
+  )
+}
+
+/// A response body, counted in flight until it is sent or dropped.
+struct InFlightBody {
+  body: Body,
+  /// `None` once the body was cut.
+  aborted: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+  _in_flight: InFlight,
+}
+
+impl HttpBody for InFlightBody {
+  type Data = Bytes;
+  type Error = axum::Error;
+
+  fn poll_frame(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<std::result::Result<Frame<Self::Data>, Self::Error>>> {
+    let Some(abort) = self.aborted.as_mut() else {
+      return Poll::Ready(None);
+    };
+    if abort.as_mut().poll(cx).is_ready() {
+      self.aborted = None;
+      return Poll::Ready(Some(Err(axum::Error::new(aborted()))));
+    }
+    Pin::new(&mut self.body).poll_frame(cx)
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.aborted.is_none() || self.body.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.body.size_hint()
+  }
+}
+
+/// Resolves on `SIGINT` (Ctrl+C) or `SIGTERM`.
+pub async fn signal() {
+  let ctrl_c = async {
+    signal::ctrl_c()
+      .await
+      .expect("Failed to install Ctrl+C handler");
+  };
+
+  #[cfg(unix)]
+  let terminate = async {
+    signal::unix::signal(signal::unix::SignalKind::terminate())
+      .expect("Failed to install SIGTERM handler")
+      .recv()
+      .await;
+  };
+
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  tokio::select! {
+      _ = ctrl_c => {},
+      _ = terminate => {},
+  }
+}
+
+/// Exits the process with [`FORCED_EXIT_CODE`] on the next signal.
+pub fn force_exit_on_signal() {
+  tokio::spawn(async {
+    signal().await;
+    eprintln!("Second signal received, exiting immediately");
+    std::process::exit(FORCED_EXIT_CODE);
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::testing::offline_context;
+  use crate::config::Config;
+
+  #[tokio::test]
+  async fn hooks_run_last_registered_first() {
+    let ctx = offline_context(Config::default()).await;
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for name in ["database", "failing", "jobs"] {
+      let order = order.clone();
+      ctx.shutdown.register(name, move |_ctx| async move {
+        order.lock().unwrap().push(name);
+        if name == "failing" {
+          return Err(crate::Error::Message("boom".to_string()));
+        }
+        Ok(())
+      });
+    }
+    assert_eq!(ctx.shutdown.hooks(), ["jobs", "failing", "database"]);
+
+    ctx.shutdown.run_hooks(&ctx).await;
+    assert_eq!(*order.lock().unwrap(), ["jobs", "failing", "database"]);
+    assert!(ctx.shutdown.hooks().is_empty());
+  }
+
+  /// Sends one chunk, then nothing until dropped.
+  struct Streaming(bool);
+
+  impl HttpBody for Streaming {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+      mut self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Self::Data>, Self::Error>>> {
+      if std::mem::replace(&mut self.0, true) {
+        Poll::Pending
+      } else {
+        Poll::Ready(Some(Ok(Frame::data(Bytes::from("receipt")))))
+      }
+    }
+  }
+
+  fn router(shutdown: &Shutdown) -> axum::Router {
+    axum::Router::new()
+      .route(
+        "/stream",
+        axum::routing::get(|| async { Body::new(Streaming(false)) }),
+      )
+      .route(
+        "/slow",
+        axum::routing::get(std::future::pending::<&'static str>),
+      )
+      .layer(axum::middleware::from_fn_with_state(
+        shutdown.clone(),
+        track_in_flight,
+      ))
+  }
+
+  #[tokio::test]
+  async fn counts_bodies_until_sent_and_aborts_them() {
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    let shutdown = Shutdown::new();
+    let request = |uri| Request::get(uri).body(Body::empty()).unwrap();
+
+    let response = router(&shutdown).oneshot(request("/stream")).await.unwrap();
+    assert_eq!(shutdown.in_flight(), 1);
+    let mut body = response.into_body();
+    let chunk = body.frame().await.unwrap().unwrap();
+    assert_eq!(chunk.into_data().unwrap(), "receipt");
+    assert_eq!(shutdown.in_flight(), 1);
+
+    let slow = tokio::spawn(router(&shutdown).oneshot(request("/slow")));
+    while shutdown.in_flight() < 2 {
+      tokio::task::yield_now().await;
+    }
+    shutdown.abort_in_flight();
+    let response = slow.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.frame().await.unwrap().is_err());
+    assert!(body.frame().await.is_none());
+
+    drop(body);
+    tokio::time::timeout(std::time::Duration::from_secs(1), shutdown.idle())
+      .await
+      .unwrap();
+    assert_eq!(shutdown.in_flight(), 0);
+  }
+
+  #[test]
+  fn counts_requests_in_flight() {
+    let shutdown = Shutdown::new();
+    let first = shutdown.track();
+    let second = shutdown.track();
+    assert_eq!(shutdown.in_flight(), 2);
+    drop(first);
+    drop(second);
+    assert_eq!(shutdown.in_flight(), 0);
+  }
+}
@@ -0,0 +1,115 @@
+// Run against the in-memory SQLite database of `AppContext::builder`
+#![cfg(feature = "db-sqlite")]
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use pos_rust_local_backend::app::{create_app, Hooks, Initializer};
+use pos_rust_local_backend::config::app_context::AppContext;
+use pos_rust_local_backend::config::extensions::Ext;
+use pos_rust_local_backend::config::routes_config::{AppRoutes, Routes};
+use pos_rust_local_backend::config::routing::get;
+use pos_rust_local_backend::Result;
+use std::sync::Mutex;
+use tower::ServiceExt;
+
+struct Calls(Mutex<Vec<&'static str>>);
+
+struct Greeting(&'static str);
+
+struct GreetingInitializer;
+
+#[async_trait]
+impl Initializer for GreetingInitializer {
+  fn name(&self) -> String {
+    "greeting".to_string()
+  }
+
+  async fn before_run(&self, ctx: &mut AppContext) -> Result<()> {
+    ctx
+      .extensions
+      .get::<Calls>()
+      .unwrap()
+      .0
+      .lock()
+      .unwrap()
+      .push("before_run");
+    ctx.extensions.insert(Greeting("hello"));
+    Ok(())
+  }
+
+  async fn after_routes(&self, router: Router, ctx: &AppContext) -> Result<Router> {
+    ctx
+      .extensions
+      .get::<Calls>()
+      .unwrap()
+      .0
+      .lock()
+      .unwrap()
+      .push("initializer.after_routes");
+    Ok(router)
+  }
+}
+
+struct App;
+
+#[async_trait]
+impl Hooks for App {
+  fn routes(ctx: &AppContext) -> AppRoutes {
+    ctx
+      .extensions
+      .get::<Calls>()
+      .unwrap()
+      .0
+      .lock()
+      .unwrap()
+      .push("routes");
+    AppRoutes::with_default_routes().add_route(Routes::at("/greeting").add(
+      "/",
+      get(|Ext(greeting): Ext<Greeting>| async move { greeting.0 }),
+    ))
+  }
+
+  async fn after_routes(router: Router, ctx: &AppContext) -> Result<Router> {
+    ctx
+      .extensions
+      .get::<Calls>()
+      .unwrap()
+      .0
+      .lock()
+      .unwrap()
+      .push("after_routes");
+    Ok(router)
+  }
+
+  async fn initializers(_ctx: &AppContext) -> Result<Vec<Box<dyn Initializer>>> {
+    Ok(vec![Box::new(GreetingInitializer)])
+  }
+}
+
+#[tokio::test]
+async fn runs_hooks_in_order() {
+  let mut ctx = AppContext::builder()
+    .extension(Calls(Mutex::new(vec![])))
+    .build()
+    .await
+    .unwrap();
+
+  let router = create_app::<App>(&mut ctx).await.unwrap();
+  assert_eq!(
+    *ctx.extensions.get::<Calls>().unwrap().0.lock().unwrap(),
+    vec![
+      "before_run",
+      "routes",
+      "initializer.after_routes",
+      "after_routes"
+    ]
+  );
+
+  let response = router
+    .oneshot(Request::get("/greeting").body(Body::empty()).unwrap())
+    .await
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,73 @@
+// Run against the in-memory SQLite database of `AppContext::builder`
+#![cfg(feature = "db-sqlite")]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use pos_rust_local_backend::config::api_version::ApiVersion;
+use pos_rust_local_backend::config::app_context::AppContext;
+use pos_rust_local_backend::config::routes_config::{AppRoutes, Routes};
+use pos_rust_local_backend::config::routing;
+use pos_rust_local_backend::config::static_assets::StaticAssets;
+use pos_rust_local_backend::controllers::tasks_controller;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+/// The directory is removed once the returned [`TempDir`] drops.
+async fn frontend(routes: AppRoutes) -> (Router, TempDir) {
+  let dir = tempfile::tempdir().unwrap();
+  std::fs::write(dir.path().join("index.html"), "<html>till</html>").unwrap();
+  let ctx = AppContext::builder().build().await.unwrap();
+  let router = routes
+    .assets(StaticAssets::dir(dir.path()))
+    .into_router(&ctx)
+    .unwrap();
+  (router, dir)
+}
+
+async fn get(router: &Router, uri: &str) -> (StatusCode, String) {
+  let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+  let response = router.clone().oneshot(request).await.unwrap();
+  let status = response.status();
+  let body = response.into_body().collect().await.unwrap().to_bytes();
+  (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn serves_the_frontend_next_to_the_api() {
+  let (router, _dir) =
+    frontend(AppRoutes::with_default_routes().add_route(tasks_controller::routes())).await;
+
+  assert_eq!(
+    get(&router, "/").await,
+    (StatusCode::OK, "<html>till</html>".to_string())
+  );
+  assert_eq!(
+    get(&router, "/orders/42").await,
+    (StatusCode::OK, "<html>till</html>".to_string())
+  );
+  assert_eq!(get(&router, "/_health").await.0, StatusCode::OK);
+  assert_eq!(get(&router, "/api/tasks").await.0, StatusCode::OK);
+  let (status, body) = get(&router, "/api/unknown").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert!(body.contains("not_found"), "{body}");
+}
+
+#[tokio::test]
+async fn serves_the_frontend_behind_the_versions() {
+  let v1 =
+    ApiVersion::new("v1").add_route(Routes::at("/tasks").add("/", routing::get(|| async {})));
+  let (router, _dir) = frontend(AppRoutes::with_default_routes().add_version(v1)).await;
+
+  assert_eq!(get(&router, "/api/v1/tasks").await.0, StatusCode::OK);
+  assert_eq!(get(&router, "/api/tasks").await.0, StatusCode::OK);
+  assert_eq!(
+    get(&router, "/api/v1/unknown").await.0,
+    StatusCode::NOT_FOUND
+  );
+  assert_eq!(
+    get(&router, "/orders/42").await,
+    (StatusCode::OK, "<html>till</html>".to_string())
+  );
+}
@@ -0,0 +1,80 @@
+// Run against the in-memory SQLite database of `AppContext::builder`
+#![cfg(feature = "db-sqlite")]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use pos_rust_local_backend::config::app_context::AppContext;
+use pos_rust_local_backend::config::routes_config::AppRoutes;
+use pos_rust_local_backend::config::{Config, Environment, MigrationPolicy};
+use pos_rust_local_backend::controllers::tasks_controller;
+use serde_json::Value;
+use tower::ServiceExt;
+
+async fn get(uri: &str) -> (StatusCode, String) {
+  get_with(Config::for_environment(&Environment::Test), uri).await
+}
+
+async fn get_with(config: Config, uri: &str) -> (StatusCode, String) {
+  let ctx = AppContext::builder().config(config).build().await.unwrap();
+  let router = AppRoutes::with_default_routes()
+    .add_route(tasks_controller::routes())
+    .into_router(&ctx)
+    .unwrap();
+  let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+  let response = router.oneshot(request).await.unwrap();
+  let status = response.status();
+  let body = response.into_body().collect().await.unwrap().to_bytes();
+  (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn serves_the_openapi_document() {
+  let (status, body) = get("/api/openapi.json").await;
+  assert_eq!(status, StatusCode::OK);
+  let document: Value = serde_json::from_str(&body).unwrap();
+
+  assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+  let create = &document["paths"]["/api/tasks"]["post"];
+  assert_eq!(create["operationId"], "create_task");
+  assert_eq!(
+    create["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+    "#/components/schemas/CreateTask"
+  );
+  let update = &document["paths"]["/api/tasks/{id}"]["patch"];
+  assert_eq!(
+    update["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+    "#/components/schemas/UpdateTask"
+  );
+  for schema in [
+    "CreateTask",
+    "UpdateTask",
+    "CreatedTask",
+    "Task",
+    "ErrorDetail",
+  ] {
+    assert!(
+      document["components"]["schemas"][schema].is_object(),
+      "missing schema {schema}"
+    );
+  }
+  let required = &document["components"]["schemas"]["CreateTask"]["required"];
+  assert_eq!(*required, serde_json::json!(["title", "description"]));
+}
+
+#[tokio::test]
+async fn serves_swagger_ui() {
+  let (status, body) = get("/api/docs/").await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(body.contains("swagger-ui"), "{body}");
+}
+
+#[tokio::test]
+async fn production_hides_the_docs() {
+  let mut config = Config::for_environment(&Environment::Production);
+  config.database.migrations = MigrationPolicy::Up;
+  for uri in ["/api/openapi.json", "/api/docs/"] {
+    let (status, _) = get_with(config.clone(), uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+  }
+}
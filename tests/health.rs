@@ -0,0 +1,108 @@
+// Run against the in-memory SQLite database of `AppContext::builder`
+#![cfg(feature = "db-sqlite")]
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use migration::{Migrator, MigratorTrait};
+use pos_rust_local_backend::cache::drivers::CacheDriver;
+use pos_rust_local_backend::cache::{self, CacheError, CacheResult};
+use pos_rust_local_backend::config::app_context::AppContext;
+use pos_rust_local_backend::config::routes_config::AppRoutes;
+use serde_json::Value;
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// A cache whose server is down.
+struct Unreachable;
+
+#[async_trait]
+impl CacheDriver for Unreachable {
+  async fn contains_key(&self, _key: &str) -> CacheResult<bool> {
+    Err(CacheError::Any("connection refused".into()))
+  }
+
+  async fn get(&self, _key: &str) -> CacheResult<Option<String>> {
+    Err(CacheError::Any("connection refused".into()))
+  }
+
+  async fn insert(&self, _key: &str, _value: &str) -> CacheResult<()> {
+    Err(CacheError::Any("connection refused".into()))
+  }
+
+  async fn insert_with_expiry(&self, _key: &str, _value: &str, _: Duration) -> CacheResult<()> {
+    Err(CacheError::Any("connection refused".into()))
+  }
+
+  async fn remove(&self, _key: &str) -> CacheResult<()> {
+    Err(CacheError::Any("connection refused".into()))
+  }
+
+  async fn clear(&self) -> CacheResult<()> {
+    Err(CacheError::Any("connection refused".into()))
+  }
+}
+
+async fn get(router: &axum::Router, uri: &str) -> (StatusCode, Value) {
+  let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+  let response = router.clone().oneshot(request).await.unwrap();
+  let status = response.status();
+  let bytes = response.into_body().collect().await.unwrap().to_bytes();
+  (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn reports_health_and_readiness() {
+  let ctx = AppContext::builder().build().await.unwrap();
+  let router = AppRoutes::with_default_routes().into_router(&ctx).unwrap();
+
+  let (status, health) = get(&router, "/_health").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(health["ok"], true);
+
+  let (status, ready) = get(&router, "/_ready").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(ready["ok"], true);
+  for check in ["database", "cache", "migrations"] {
+    assert_eq!(ready["checks"][check]["status"], "ok", "{check}: {ready}");
+    assert!(ready["checks"][check]["latency_ms"].is_f64());
+  }
+}
+
+#[tokio::test]
+async fn is_not_ready_with_pending_migrations_or_a_broken_cache() {
+  let ctx = AppContext::builder()
+    .cache(Box::new(Unreachable))
+    .build()
+    .await
+    .unwrap();
+  Migrator::down(&ctx.db, Some(1)).await.unwrap();
+  let router = AppRoutes::with_default_routes().into_router(&ctx).unwrap();
+
+  let (status, ready) = get(&router, "/_ready").await;
+  assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+  assert_eq!(ready["ok"], false);
+  assert_eq!(ready["checks"]["database"]["status"], "ok");
+  assert_eq!(ready["checks"]["cache"]["status"], "error");
+  assert_eq!(ready["checks"]["migrations"]["status"], "error");
+  // The reasons stay in the logs
+  for check in ["database", "cache", "migrations"] {
+    assert!(ready["checks"][check].get("message").is_none(), "{ready}");
+  }
+}
+
+#[tokio::test]
+async fn reports_the_null_cache_as_disabled() {
+  // Injected, while the configuration still names the in-memory cache
+  let ctx = AppContext::builder()
+    .cache(cache::drivers::null::new())
+    .build()
+    .await
+    .unwrap();
+  let router = AppRoutes::with_default_routes().into_router(&ctx).unwrap();
+
+  let (status, ready) = get(&router, "/_ready").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(ready["checks"]["cache"]["status"], "disabled");
+}
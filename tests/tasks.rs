@@ -0,0 +1,90 @@
+// Run against the in-memory SQLite database of `AppContext::builder`
+#![cfg(feature = "db-sqlite")]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use http_body_util::BodyExt;
+use pos_rust_local_backend::config::app_context::AppContext;
+use pos_rust_local_backend::config::routes_config::AppRoutes;
+use pos_rust_local_backend::controllers::tasks_controller;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+async fn request(
+  router: &axum::Router,
+  method: &str,
+  uri: &str,
+  body: Option<Value>,
+) -> (StatusCode, Value) {
+  let builder = Request::builder().method(method).uri(uri);
+  let request = match body {
+    Some(body) => builder
+      .header("content-type", "application/json")
+      .body(Body::from(body.to_string())),
+    None => builder.body(Body::empty()),
+  }
+  .unwrap();
+
+  let response = router.clone().oneshot(request).await.unwrap();
+  let status = response.status();
+  let bytes = response.into_body().collect().await.unwrap().to_bytes();
+  (
+    status,
+    serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+  )
+}
+
+#[tokio::test]
+async fn can_create_and_list_tasks() {
+  let ctx = AppContext::builder().build().await.unwrap();
+  let router = AppRoutes::with_default_routes()
+    .add_route(tasks_controller::routes())
+    .into_router(&ctx)
+    .unwrap();
+
+  let (status, created) = request(
+    &router,
+    "POST",
+    "/api/tasks",
+    Some(json!({ "title": "count till", "description": "before closing" })),
+  )
+  .await;
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(created["title"], "count till");
+
+  let (status, tasks) = request(&router, "GET", "/api/tasks", None).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    tasks,
+    json!([{ "id": created["id"], "title": "count till", "description": "before closing" }])
+  );
+}
+
+#[tokio::test]
+async fn can_get_a_task() {
+  let ctx = AppContext::builder().build().await.unwrap();
+  let router = AppRoutes::with_default_routes()
+    .add_route(tasks_controller::routes())
+    .into_router(&ctx)
+    .unwrap();
+
+  let (_, created) = request(
+    &router,
+    "POST",
+    "/api/tasks",
+    Some(json!({ "title": "count till", "description": "before closing" })),
+  )
+  .await;
+  let uri = format!("/api/tasks/{}", created["id"]);
+  let (status, task) = request(&router, "GET", &uri, None).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    task,
+    json!([{ "id": created["id"], "title": "count till", "description": "before closing" }])
+  );
+
+  // An empty list rather than a 404, as the tills expect
+  let (status, task) = request(&router, "GET", "/api/tasks/999", None).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(task, json!([]));
+}
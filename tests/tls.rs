@@ -0,0 +1,200 @@
+// Run against the in-memory SQLite database of `AppContext::builder`
+#![cfg(feature = "db-sqlite")]
+
+use pos_rust_local_backend::config::app_context::AppContext;
+use pos_rust_local_backend::config::listener::AppListener;
+use pos_rust_local_backend::config::routes_config::AppRoutes;
+use pos_rust_local_backend::config::Tls;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+struct Authority {
+  cert: Certificate,
+  key: KeyPair,
+}
+
+impl Authority {
+  fn new() -> Self {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![]).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let cert = params.self_signed(&key).unwrap();
+    Self { cert, key }
+  }
+
+  fn issue(&self, name: &str) -> (Certificate, KeyPair) {
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec![name.to_string()])
+      .unwrap()
+      .signed_by(&key, &self.cert, &self.key)
+      .unwrap();
+    (cert, key)
+  }
+}
+
+fn write_pair(dir: &Path, name: &str, cert: &Certificate, key: &KeyPair) -> (PathBuf, PathBuf) {
+  let cert_path = dir.join(format!("{name}.crt"));
+  let key_path = dir.join(format!("{name}.key"));
+  std::fs::write(&cert_path, cert.pem()).unwrap();
+  std::fs::write(&key_path, key.serialize_pem()).unwrap();
+  (cert_path, key_path)
+}
+
+/// Serves until `signal` resolves.
+async fn serve_until(
+  tls: &Tls,
+  signal: impl Future<Output = ()> + Send + 'static,
+) -> (SocketAddr, JoinHandle<std::io::Result<()>>) {
+  let ctx = AppContext::builder().build().await.unwrap();
+  let router = AppRoutes::with_default_routes().into_router(&ctx).unwrap();
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let listener = AppListener::Tcp(listener).with_tls(tls).unwrap();
+  assert_eq!(listener.to_string(), format!("https://{addr}"));
+  (addr, tokio::spawn(listener.serve(router, signal)))
+}
+
+async fn serve(tls: &Tls) -> SocketAddr {
+  serve_until(tls, std::future::pending()).await.0
+}
+
+/// Sends `GET /_health` and returns the status line, or `None` when the
+/// server refused the connection.
+async fn get_health(
+  addr: SocketAddr,
+  ca: &Authority,
+  client: Option<&(Certificate, KeyPair)>,
+) -> Option<String> {
+  let mut roots = RootCertStore::empty();
+  roots.add(ca.cert.der().clone()).unwrap();
+  let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots);
+  let config = match client {
+    Some((cert, key)) => builder
+      .with_client_auth_cert(
+        vec![cert.der().clone()],
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+      )
+      .unwrap(),
+    None => builder.with_no_client_auth(),
+  };
+
+  let stream = TcpStream::connect(addr).await.unwrap();
+  let name = ServerName::try_from("localhost").unwrap();
+  let mut stream = TlsConnector::from(Arc::new(config))
+    .connect(name, stream)
+    .await
+    .ok()?;
+  stream
+    .write_all(b"GET /_health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+    .await
+    .ok()?;
+  let mut response = String::new();
+  stream.read_to_string(&mut response).await.ok()?;
+  response.lines().next().map(ToString::to_string)
+}
+
+#[tokio::test]
+async fn serves_https_and_reloads_renewed_certificates() {
+  let dir = TempDir::new().unwrap();
+  let ca = Authority::new();
+  let (server_cert, server_key) = ca.issue("localhost");
+  let (cert, key) = write_pair(dir.path(), "server", &server_cert, &server_key);
+  let tls = Tls {
+    cert,
+    key,
+    client_ca: None,
+    reload_interval: 50,
+  };
+  let addr = serve(&tls).await;
+  assert_eq!(
+    get_health(addr, &ca, None).await.as_deref(),
+    Some("HTTP/1.1 200 OK")
+  );
+
+  // Renewed by another authority: only clients trusting it connect
+  let renewed_ca = Authority::new();
+  let (renewed_cert, renewed_key) = renewed_ca.issue("localhost");
+  write_pair(dir.path(), "server", &renewed_cert, &renewed_key);
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  assert_eq!(get_health(addr, &ca, None).await, None);
+  assert_eq!(
+    get_health(addr, &renewed_ca, None).await.as_deref(),
+    Some("HTTP/1.1 200 OK")
+  );
+}
+
+#[tokio::test]
+async fn mutual_tls_only_accepts_enrolled_terminals() {
+  let dir = TempDir::new().unwrap();
+  let ca = Authority::new();
+  let (server_cert, server_key) = ca.issue("localhost");
+  let (cert, key) = write_pair(dir.path(), "server", &server_cert, &server_key);
+  let terminals = Authority::new();
+  let client_ca = dir.path().join("terminals.crt");
+  std::fs::write(&client_ca, terminals.cert.pem()).unwrap();
+  let tls = Tls {
+    cert,
+    key,
+    client_ca: Some(client_ca),
+    reload_interval: 0,
+  };
+  let addr = serve(&tls).await;
+
+  let enrolled = terminals.issue("till-1");
+  assert_eq!(
+    get_health(addr, &ca, Some(&enrolled)).await.as_deref(),
+    Some("HTTP/1.1 200 OK")
+  );
+  assert_eq!(get_health(addr, &ca, None).await, None);
+  let stranger = Authority::new().issue("till-2");
+  assert_eq!(get_health(addr, &ca, Some(&stranger)).await, None);
+}
+
+#[tokio::test]
+async fn stops_accepting_once_the_shutdown_starts() {
+  let dir = TempDir::new().unwrap();
+  let ca = Authority::new();
+  let (server_cert, server_key) = ca.issue("localhost");
+  let (cert, key) = write_pair(dir.path(), "server", &server_cert, &server_key);
+  let tls = Tls {
+    cert,
+    key,
+    client_ca: None,
+    reload_interval: 0,
+  };
+  let (stop, stopped) = oneshot::channel::<()>();
+  let (addr, server) = serve_until(&tls, async {
+    let _ = stopped.await;
+  })
+  .await;
+  // Connected, but silent: its handshake is under way
+  let mut silent = TcpStream::connect(addr).await.unwrap();
+  assert_eq!(
+    get_health(addr, &ca, None).await.as_deref(),
+    Some("HTTP/1.1 200 OK")
+  );
+
+  stop.send(()).unwrap();
+  server.await.unwrap().unwrap();
+  // Dropped rather than left to the handshake timeout
+  let mut buf = [0; 1];
+  let read = tokio::time::timeout(Duration::from_secs(2), silent.read(&mut buf)).await;
+  assert!(matches!(read, Ok(Ok(0) | Err(_))), "{read:?}");
+  assert!(TcpStream::connect(addr).await.is_err());
+}